@@ -0,0 +1,96 @@
+use crate::AppState;
+use crate::db;
+use crate::db::users::User;
+use crate::error::{self, AppError};
+use crate::token;
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use std::fmt;
+use std::str::FromStr;
+use strum::{Display, EnumString, IntoStaticStr};
+
+/// Makes leaked tokens easy to recognize, e.g. by secret scanners
+const TOKEN_PREFIX: &str = "jpat_";
+
+#[derive(Clone, Copy, Debug, Display, EnumString, IntoStaticStr, PartialEq)]
+pub enum Scope {
+    #[strum(serialize = "entries:read")]
+    EntriesRead,
+
+    #[strum(serialize = "entries:write")]
+    EntriesWrite,
+}
+
+/// Generates a new token, returning it together with the hash to store
+pub fn generate_access_token() -> (String, Vec<u8>) {
+    let access_token = format!("{}{}", TOKEN_PREFIX, token::generate_token());
+    let hash = token::hash_token(&access_token);
+    (access_token, hash)
+}
+
+/// The user of a request authenticated with an `Authorization: Bearer` personal access token
+pub struct BearerAuth {
+    pub user: User,
+    scopes: Vec<Scope>,
+}
+
+impl BearerAuth {
+    #[track_caller]
+    pub fn require(&self, scope: Scope) -> Result<(), AppError> {
+        if self.scopes.contains(&scope) {
+            Ok(())
+        } else {
+            Err(error::insufficient_scope(scope.into()))
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for BearerAuth
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let access_token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|value| value.starts_with(TOKEN_PREFIX))
+            .ok_or_else(error::unauthorized_error)?;
+
+        // the connection is released before the handler takes its own, the pool only holds one
+        let found = {
+            let app_state = AppState::from_ref(state);
+            let db_conn = app_state.postgres_pool.get_owned().await?;
+            db::access_tokens::touch_access_token(&db_conn, &token::hash_token(access_token))
+                .await?
+        };
+
+        let (user, scopes) = found
+            .filter(|(user, _)| !user.disabled)
+            .ok_or_else(error::unauthorized_error)?;
+        Ok(BearerAuth {
+            user,
+            // scopes that are no longer known are ignored
+            scopes: scopes
+                .iter()
+                .filter_map(|scope| Scope::from_str(scope).ok())
+                .collect(),
+        })
+    }
+}
+
+impl fmt::Debug for BearerAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // keep the password hash out of traces
+        f.debug_struct("BearerAuth")
+            .field("user_id", &self.user.id)
+            .field("scopes", &self.scopes)
+            .finish()
+    }
+}
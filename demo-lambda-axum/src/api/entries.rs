@@ -0,0 +1,136 @@
+use crate::AppState;
+use crate::access_token::{BearerAuth, Scope};
+use crate::db;
+use crate::db::DatabaseConnection;
+use crate::db::entries::{Entry, SearchFilter, SearchResult};
+use crate::error::{self, AppError};
+use crate::extract::ValidatedJson;
+use crate::htm::{PAGE_SIZE, Page, page_offset};
+use crate::serde_decorators::empty_string_as_none;
+use crate::verification;
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use util::tags;
+use util::tracing::{self, instrument};
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Deserialize, Validate)]
+pub struct NewEntry {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    q: String,
+
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    from: Option<NaiveDate>,
+
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    to: Option<NaiveDate>,
+
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    page: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResponse {
+    results: Vec<SearchResult>,
+    page: i64,
+    has_next: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DateAndId {
+    date: NaiveDate,
+    id: Uuid,
+}
+
+#[instrument]
+pub async fn get_entries(
+    auth: BearerAuth,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Path(date): Path<NaiveDate>,
+) -> Result<Json<Vec<Entry>>, AppError> {
+    auth.require(Scope::EntriesRead)?;
+    Ok(Json(
+        db::entries::read_entries(&db_conn, &auth.user.id, &date).await?,
+    ))
+}
+
+/// The same search as the search page, paged the same way
+#[instrument]
+pub async fn get_search(
+    auth: BearerAuth,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<SearchResponse>, AppError> {
+    auth.require(Scope::EntriesRead)?;
+
+    let filter = SearchFilter {
+        query: params.q,
+        from: params.from,
+        to: params.to,
+    };
+    let (number, offset) = page_offset(params.page);
+    let results =
+        db::entries::search_entries(&db_conn, &auth.user.id, &filter, PAGE_SIZE + 1, offset)
+            .await?;
+
+    let page = Page::new(results, number);
+    Ok(Json(SearchResponse {
+        results: page.entries,
+        page: page.number,
+        has_next: page.has_next,
+    }))
+}
+
+#[instrument(skip(state, entry))]
+pub async fn post_entry(
+    State(state): State<AppState>,
+    auth: BearerAuth,
+    DatabaseConnection(mut db_conn): DatabaseConnection,
+    Path(date): Path<NaiveDate>,
+    ValidatedJson(entry): ValidatedJson<NewEntry>,
+) -> Result<(StatusCode, Json<Entry>), AppError> {
+    auth.require(Scope::EntriesWrite)?;
+    verification::require_verified_email(&state.config.email_verification, &auth.user)?;
+
+    let id = Uuid::now_v7();
+    let transaction = db_conn.transaction().await?;
+    db::entries::create_entry(&transaction, &auth.user.id, &date, &id, &entry.content).await?;
+    let tags = tags::parse_tags(&entry.content);
+    db::entries::update_entry_tags(&transaction, &auth.user.id, &date, &id, &tags).await?;
+    transaction.commit().await?;
+    tracing::info!(user = auth.user.name, "Created an entry through the API");
+
+    Ok((
+        StatusCode::CREATED,
+        Json(Entry {
+            date,
+            id,
+            content: entry.content,
+            tags,
+        }),
+    ))
+}
+
+#[instrument(skip(state))]
+pub async fn delete_entry(
+    State(state): State<AppState>,
+    auth: BearerAuth,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Path(params): Path<DateAndId>,
+) -> Result<StatusCode, AppError> {
+    auth.require(Scope::EntriesWrite)?;
+    verification::require_verified_email(&state.config.email_verification, &auth.user)?;
+    if !db::entries::delete_entry(&db_conn, &auth.user.id, &params.date, &params.id).await? {
+        return Err(error::not_found_error());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
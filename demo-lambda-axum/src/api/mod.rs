@@ -7,6 +7,8 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use util::tracing::{self, instrument};
 
+pub mod entries;
+
 #[derive(Deserialize)]
 pub struct GreetingParams {
     #[serde(deserialize_with = "empty_string_as_none")]
@@ -28,8 +30,7 @@ pub async fn get_hello(
         value: format!(
             "Hola {}!",
             params
-                .map(|p| p.name)
-                .flatten()
+                .and_then(|p| p.name)
                 .unwrap_or_else(|| "mundo".to_owned())
         ),
         timestamp: Utc::now(),
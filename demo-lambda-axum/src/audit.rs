@@ -0,0 +1,99 @@
+use crate::db;
+use crate::db::PostgresPooledConnection;
+use crate::db::audit_events::NewAuditEvent;
+use crate::error::AppError;
+use crate::extract::ClientInfo;
+use strum::{Display, EnumIter, EnumString, IntoStaticStr};
+use uuid::Uuid;
+
+/// The security relevant events written to the audit log
+#[derive(Clone, Copy, Debug, Display, EnumIter, EnumString, IntoStaticStr, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum AuditEvent {
+    Login,
+    LoginFailed,
+    Logout,
+    LogoutAll,
+    SessionRevoked,
+    PasswordReset,
+    PasswordChanged,
+    UsernameChanged,
+    EmailChanged,
+    TotpEnabled,
+    TotpDisabled,
+    PasskeyAdded,
+    PasskeyDeleted,
+    AccessTokenCreated,
+    AccessTokenDeleted,
+    UserDisabled,
+    UserEnabled,
+    DataExported,
+    DeletionScheduled,
+    DeletionCancelled,
+    InviteCodeCreated,
+}
+
+/// Records an event the user caused on their own account
+pub async fn record(
+    db_conn: &PostgresPooledConnection,
+    client: &ClientInfo,
+    event: AuditEvent,
+    user_id: &Uuid,
+) -> Result<(), AppError> {
+    insert(db_conn, client, event, Some(user_id), None, None).await
+}
+
+/// Records an event an administrator caused on the account of another user
+pub async fn record_admin_action(
+    db_conn: &PostgresPooledConnection,
+    client: &ClientInfo,
+    event: AuditEvent,
+    admin_id: &Uuid,
+    user_id: &Uuid,
+) -> Result<(), AppError> {
+    insert(db_conn, client, event, Some(user_id), Some(admin_id), None).await
+}
+
+/// Records a rejected login. The attempt is added to the history of the account it targeted, if
+/// that exists, and keeps the entered name for the admin view either way.
+pub async fn record_failed_login(
+    db_conn: &PostgresPooledConnection,
+    client: &ClientInfo,
+    username: &String,
+    reason: &str,
+) -> Result<(), AppError> {
+    let user = db::users::get_user_by_name(db_conn, username).await?;
+    let detail = format!("{} ({})", reason, username);
+    insert(
+        db_conn,
+        client,
+        AuditEvent::LoginFailed,
+        user.as_ref().map(|user| &user.id),
+        None,
+        Some(&detail),
+    )
+    .await
+}
+
+async fn insert(
+    db_conn: &PostgresPooledConnection,
+    client: &ClientInfo,
+    event: AuditEvent,
+    user_id: Option<&Uuid>,
+    actor_id: Option<&Uuid>,
+    detail: Option<&str>,
+) -> Result<(), AppError> {
+    db::audit_events::create_audit_event(
+        db_conn,
+        &NewAuditEvent {
+            event: event.into(),
+            user_id,
+            actor_id,
+            detail,
+            ip: client.ip.as_deref(),
+            user_agent: client.user_agent.as_deref(),
+            request_id: client.request_id.as_deref(),
+        },
+    )
+    .await
+}
@@ -0,0 +1,229 @@
+use crate::AppState;
+use axum::extract::{FromRef, Request, State};
+use axum::http::header::COOKIE;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum_extra::extract::cookie::{Cookie, Key};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use cookie::CookieJar;
+use tower_http::BoxError;
+use util::tracing;
+
+/// The keys private cookies are encrypted with. New cookies always use the primary key, the older
+/// ones are only tried for decryption so that rotating the key doesn't log everybody out.
+#[derive(Clone)]
+pub struct CookieKeys {
+    primary: Key,
+    older: Vec<Key>,
+}
+
+/// Names of the request cookies that were encrypted with an older key. [`cookie_key_middleware`]
+/// has already replaced them with their value under the primary key, their owners only have to
+/// send them again.
+#[derive(Clone, Debug)]
+pub struct RekeyedCookies(Vec<String>);
+
+impl RekeyedCookies {
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|rekeyed| rekeyed == name)
+    }
+}
+
+impl CookieKeys {
+    /// Decodes the keys, the first one being the primary key
+    pub fn from_base64(keys_base64: &[String]) -> Result<Self, BoxError> {
+        let mut keys = keys_base64
+            .iter()
+            .enumerate()
+            .map(|(index, key_base64)| {
+                let key = BASE64_STANDARD.decode(key_base64.trim()).map_err(|e| {
+                    format!(
+                        "cookie_keys_base64 key {} is not valid base64: {}",
+                        index + 1,
+                        e
+                    )
+                })?;
+                Key::try_from(key.as_slice()).map_err(|_| {
+                    format!(
+                        "cookie_keys_base64 key {} must decode to at least 64 bytes",
+                        index + 1
+                    )
+                    .into()
+                })
+            })
+            .collect::<Result<Vec<Key>, BoxError>>()?;
+
+        if keys.is_empty() {
+            return Err("cookie_keys_base64 must contain at least one key".into());
+        }
+
+        let primary = keys.remove(0);
+        Ok(CookieKeys {
+            primary,
+            older: keys,
+        })
+    }
+
+    /// Re-encrypts the cookies of the headers that only an older key can decrypt, and returns
+    /// their names
+    fn rekey(&self, headers: &mut HeaderMap) -> Vec<String> {
+        let cookies: Vec<Cookie<'static>> = headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| Cookie::split_parse_encoded(value.to_owned()))
+            .filter_map(Result::ok)
+            .collect();
+
+        let mut rekeyed = Vec::new();
+        let mut jar = CookieJar::new();
+        for cookie in cookies {
+            if jar.private(&self.primary).decrypt(cookie.clone()).is_some() {
+                jar.add_original(cookie);
+                continue;
+            }
+
+            let decrypted = self
+                .older
+                .iter()
+                .find_map(|key| jar.private(key).decrypt(cookie.clone()));
+            match decrypted {
+                Some(decrypted) => {
+                    rekeyed.push(decrypted.name().to_owned());
+                    jar.private_mut(&self.primary).add_original(decrypted);
+                }
+                // not ours or tampered with, the jar extractors will ignore it as before
+                None => jar.add_original(cookie),
+            }
+        }
+
+        if rekeyed.is_empty() {
+            return rekeyed;
+        }
+
+        let header = jar
+            .iter()
+            .map(|cookie| cookie.stripped().encoded().to_string())
+            .collect::<Vec<_>>()
+            .join("; ");
+        headers.remove(COOKIE);
+        if let Ok(value) = HeaderValue::from_str(&header) {
+            headers.insert(COOKIE, value);
+        }
+        rekeyed
+    }
+}
+
+/// Lets the rest of the app see cookies encrypted with an older key as if they had been encrypted
+/// with the primary key, and tells their owners through [`RekeyedCookies`] to re-issue them.
+/// The session cookie is re-issued with every authenticated response anyway.
+pub async fn cookie_key_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let rekeyed = state.cookie_keys.rekey(request.headers_mut());
+    if !rekeyed.is_empty() {
+        tracing::info!(cookies = rekeyed.join(","), "Re-keying cookies");
+        request.extensions_mut().insert(RekeyedCookies(rekeyed));
+    }
+    next.run(request).await
+}
+
+impl FromRef<AppState> for Key {
+    fn from_ref(state: &AppState) -> Self {
+        state.cookie_keys.primary.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_base64(byte: u8) -> String {
+        BASE64_STANDARD.encode([byte; 64])
+    }
+
+    fn encrypted(key: &Key, name: &'static str, value: &'static str) -> String {
+        let mut jar = CookieJar::new();
+        jar.private_mut(key).add(Cookie::new(name, value));
+        jar.get(name).unwrap().stripped().encoded().to_string()
+    }
+
+    fn decrypted(key: &Key, headers: &HeaderMap, name: &str) -> Option<String> {
+        let mut jar = CookieJar::new();
+        let header = headers.get(COOKIE)?.to_str().ok()?.to_owned();
+        for cookie in Cookie::split_parse_encoded(header).filter_map(Result::ok) {
+            jar.add_original(cookie);
+        }
+        Some(jar.private(key).get(name)?.value().to_owned())
+    }
+
+    #[test]
+    fn rekey_re_encrypts_cookies_of_an_older_key() {
+        let keys = CookieKeys::from_base64(&[key_base64(1), key_base64(2)]).unwrap();
+        let current = encrypted(&keys.primary, "current", "a");
+        let retired = encrypted(&keys.older[0], "retired", "b");
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, format!("{current}; {retired}").parse().unwrap());
+
+        assert_eq!(keys.rekey(&mut headers), vec!["retired".to_owned()]);
+        assert_eq!(
+            decrypted(&keys.primary, &headers, "current").as_deref(),
+            Some("a")
+        );
+        assert_eq!(
+            decrypted(&keys.primary, &headers, "retired").as_deref(),
+            Some("b")
+        );
+    }
+
+    #[test]
+    fn rekey_leaves_cookies_no_key_decrypts_unreadable() {
+        let keys = CookieKeys::from_base64(&[key_base64(1), key_base64(2)]).unwrap();
+        let unknown = Key::from(&[3; 64]);
+        let retired = encrypted(&keys.older[0], "retired", "b");
+        let foreign = encrypted(&unknown, "foreign", "c");
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, format!("{retired}; {foreign}").parse().unwrap());
+
+        assert_eq!(keys.rekey(&mut headers), vec!["retired".to_owned()]);
+        assert_eq!(decrypted(&keys.primary, &headers, "foreign"), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, foreign.parse().unwrap());
+        assert!(keys.rekey(&mut headers).is_empty());
+        assert_eq!(decrypted(&keys.primary, &headers, "foreign"), None);
+    }
+
+    #[test]
+    fn from_base64_rejects_an_empty_key_list() {
+        let error = CookieKeys::from_base64(&[]).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "cookie_keys_base64 must contain at least one key"
+        );
+    }
+
+    #[test]
+    fn from_base64_rejects_malformed_and_short_keys() {
+        let error = CookieKeys::from_base64(&[key_base64(1), "not base64!".to_owned()])
+            .err()
+            .unwrap();
+        assert!(
+            error
+                .to_string()
+                .starts_with("cookie_keys_base64 key 2 is not valid base64")
+        );
+
+        let error = CookieKeys::from_base64(&[BASE64_STANDARD.encode([1; 32])])
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "cookie_keys_base64 key 1 must decode to at least 64 bytes"
+        );
+    }
+}
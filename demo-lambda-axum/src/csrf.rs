@@ -0,0 +1,219 @@
+use crate::cookie_keys::RekeyedCookies;
+use crate::error::{self, AppError};
+use crate::token;
+use axum::body::{Body, to_bytes};
+use axum::extract::Request;
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum_extra::extract::PrivateCookieJar;
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use subtle::ConstantTimeEq;
+
+const CSRF_COOKIE: &str = "csrf_token";
+const CSRF_HEADER: &str = "x-csrf-token";
+const CSRF_FIELD: &str = "csrf_token";
+const MAX_FORM_BYTES: usize = 2 * 1024 * 1024;
+
+/// The CSRF token of the request, put into the request extensions by [`csrf_middleware`] so that
+/// templates can embed it
+#[derive(Clone, Debug)]
+pub struct CsrfToken(pub String);
+
+pub async fn csrf_middleware(
+    jar: PrivateCookieJar,
+    request: Request,
+    next: Next,
+) -> Result<(PrivateCookieJar, Response), AppError> {
+    let rekeyed = request
+        .extensions()
+        .get::<RekeyedCookies>()
+        .is_some_and(|rekeyed| rekeyed.contains(CSRF_COOKIE));
+
+    let (token, jar) = match jar.get(CSRF_COOKIE) {
+        Some(cookie) if rekeyed => {
+            let token = cookie.value().to_owned();
+            (token.clone(), jar.add(csrf_cookie(token)))
+        }
+        Some(cookie) => (cookie.value().to_owned(), jar),
+        None => {
+            let token = token::generate_token();
+            (token.clone(), jar.add(csrf_cookie(token)))
+        }
+    };
+
+    let mut request = if is_exempt(&request) {
+        request
+    } else {
+        verify(request, &token).await?
+    };
+
+    request.extensions_mut().insert(CsrfToken(token));
+    Ok((jar, next.run(request).await))
+}
+
+/// Forces a fresh token to be issued on the next request, used whenever a session starts or ends
+pub fn remove_csrf_cookie(jar: PrivateCookieJar) -> PrivateCookieJar {
+    jar.remove(Cookie::build(CSRF_COOKIE).path("/"))
+}
+
+fn csrf_cookie(token: String) -> Cookie<'static> {
+    Cookie::build((CSRF_COOKIE, token))
+        .path("/")
+        .secure(true)
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .build()
+}
+
+fn is_exempt(request: &Request) -> bool {
+    if request.method().is_safe() {
+        return true;
+    }
+
+    // bearer tokens aren't sent automatically by browsers, so they can't be forged cross-site
+    request.uri().path().starts_with("/api/v1")
+        && request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("Bearer "))
+}
+
+async fn verify(request: Request, token: &str) -> Result<Request, AppError> {
+    if let Some(header) = request.headers().get(CSRF_HEADER) {
+        return if matches(header.as_bytes(), token) {
+            Ok(request)
+        } else {
+            Err(error::csrf_error())
+        };
+    }
+
+    let is_form = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+
+    if is_form {
+        // the form has to be buffered to read the field, and handed on to the handler afterwards
+        let (parts, body) = request.into_parts();
+        let bytes = to_bytes(body, MAX_FORM_BYTES)
+            .await
+            .map_err(|_| error::csrf_error())?;
+
+        let valid = form_urlencoded::parse(&bytes)
+            .find(|(name, _)| name == CSRF_FIELD)
+            .is_some_and(|(_, value)| matches(value.as_bytes(), token));
+
+        if valid {
+            return Ok(Request::from_parts(parts, Body::from(bytes)));
+        }
+    }
+
+    Err(error::csrf_error())
+}
+
+fn matches(submitted: &[u8], token: &str) -> bool {
+    submitted.ct_eq(token.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{Method, StatusCode};
+    use axum::response::IntoResponse;
+
+    const TOKEN: &str = "expected-token";
+
+    fn request(method: Method, path: &str) -> axum::http::request::Builder {
+        Request::builder().method(method).uri(path)
+    }
+
+    fn form(body: &'static str) -> Request {
+        request(Method::POST, "/htm/journal/entries")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn rejection(request: Request) -> StatusCode {
+        verify(request, TOKEN)
+            .await
+            .err()
+            .unwrap()
+            .into_response()
+            .status()
+    }
+
+    #[test]
+    fn is_exempt_allows_safe_methods() {
+        for method in [Method::GET, Method::HEAD, Method::OPTIONS] {
+            let request = request(method, "/htm/index").body(Body::empty()).unwrap();
+            assert!(is_exempt(&request));
+        }
+        let request = request(Method::POST, "/htm/index")
+            .body(Body::empty())
+            .unwrap();
+        assert!(!is_exempt(&request));
+    }
+
+    #[test]
+    fn is_exempt_allows_api_requests_with_a_bearer_token_only() {
+        let bearer = request(Method::POST, "/api/v1/entries")
+            .header(AUTHORIZATION, "Bearer abc")
+            .body(Body::empty())
+            .unwrap();
+        assert!(is_exempt(&bearer));
+
+        let cookie = request(Method::POST, "/api/v1/entries")
+            .header("cookie", "session=abc")
+            .body(Body::empty())
+            .unwrap();
+        assert!(!is_exempt(&cookie));
+
+        let htm = request(Method::POST, "/htm/journal/entries")
+            .header(AUTHORIZATION, "Bearer abc")
+            .body(Body::empty())
+            .unwrap();
+        assert!(!is_exempt(&htm));
+    }
+
+    #[tokio::test]
+    async fn verify_accepts_the_header_token() {
+        let request = request(Method::POST, "/htm/journal/entries")
+            .header(CSRF_HEADER, TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        assert!(verify(request, TOKEN).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn verify_accepts_the_form_field_and_keeps_the_body() {
+        let body = "value=hello&csrf_token=expected-token";
+        let request = verify(form(body), TOKEN).await.unwrap();
+
+        let bytes = to_bytes(request.into_body(), MAX_FORM_BYTES).await.unwrap();
+        assert_eq!(bytes, body.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn verify_rejects_a_wrong_or_missing_token() {
+        let header = request(Method::POST, "/htm/journal/entries")
+            .header(CSRF_HEADER, "wrong-token")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(rejection(header).await, StatusCode::FORBIDDEN);
+
+        assert_eq!(
+            rejection(form("value=hello&csrf_token=wrong-token")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(rejection(form("value=hello")).await, StatusCode::FORBIDDEN);
+
+        let missing = request(Method::POST, "/htm/journal/entries")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(rejection(missing).await, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::db::PostgresPooledConnection;
+use crate::db::users::{USER_COLUMNS, User, row_to_user};
+use crate::error::AppError;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+pub struct AccessToken {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+pub async fn read_access_tokens(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+) -> Result<Vec<AccessToken>, AppError> {
+    let rows = db_conn
+        .query(
+            "select id, name, scopes, created_at, last_used_at from access_tokens \
+             where user_id=$1 order by created_at",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| AccessToken {
+            id: row.get("id"),
+            name: row.get("name"),
+            scopes: row.get("scopes"),
+            created_at: row.get("created_at"),
+            last_used_at: row.get("last_used_at"),
+        })
+        .collect())
+}
+
+pub async fn create_access_token(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    name: &String,
+    token_hash: &[u8],
+    scopes: &[String],
+) -> Result<Uuid, AppError> {
+    let id = Uuid::now_v7();
+    db_conn
+        .execute(
+            "insert into access_tokens (id, user_id, name, token_hash, scopes) \
+             values ($1, $2, $3, $4, $5)",
+            &[&id, &user_id, &name, &token_hash, &scopes],
+        )
+        .await?;
+
+    Ok(id)
+}
+
+pub async fn delete_access_token(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    id: &Uuid,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "delete from access_tokens where id=$1 and user_id=$2",
+            &[&id, &user_id],
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_user_access_tokens(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+) -> Result<(), AppError> {
+    db_conn
+        .execute("delete from access_tokens where user_id=$1", &[&user_id])
+        .await?;
+
+    Ok(())
+}
+
+/// Looks up the owner and scopes of a token, and records its use
+pub async fn touch_access_token(
+    db_conn: &PostgresPooledConnection,
+    token_hash: &[u8],
+) -> Result<Option<(User, Vec<String>)>, AppError> {
+    let row = db_conn
+        .query_opt(
+            &format!(
+                "with token as ( \
+                    update access_tokens set last_used_at=current_timestamp \
+                    where token_hash=$1 \
+                    returning user_id, scopes \
+                ) \
+                select {}, token.scopes from token join users on users.id=token.user_id",
+                USER_COLUMNS
+            ),
+            &[&token_hash],
+        )
+        .await?;
+
+    Ok(row.map(|row| {
+        let scopes = row.get("scopes");
+        (row_to_user(row), scopes)
+    }))
+}
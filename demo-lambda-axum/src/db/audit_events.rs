@@ -0,0 +1,138 @@
+use crate::db::PostgresPooledConnection;
+use crate::error::AppError;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+pub struct AuditEntry {
+    pub created_at: NaiveDateTime,
+    pub event: String,
+    /// Name of the user the event is about, if any and not deleted since
+    pub user_name: Option<String>,
+    /// Name of the administrator who caused the event
+    pub actor_name: Option<String>,
+    pub detail: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+}
+
+pub struct NewAuditEvent<'a> {
+    pub event: &'a str,
+    pub user_id: Option<&'a Uuid>,
+    pub actor_id: Option<&'a Uuid>,
+    pub detail: Option<&'a str>,
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub request_id: Option<&'a str>,
+}
+
+/// Narrows the admin view, every filter left empty matches all events
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub event: Option<String>,
+    pub user_name: Option<String>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+const ENTRY_COLUMNS: &str = "audit_events.created_at, audit_events.event, \
+     users.name as user_name, actors.name as actor_name, audit_events.detail, \
+     audit_events.ip, audit_events.user_agent, audit_events.request_id";
+
+pub async fn create_audit_event(
+    db_conn: &PostgresPooledConnection,
+    event: &NewAuditEvent<'_>,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "insert into audit_events (id, event, user_id, actor_id, detail, ip, user_agent, request_id) \
+             values ($1, $2, $3, $4, $5, $6, $7, $8)",
+            &[
+                &Uuid::now_v7(),
+                &event.event,
+                &event.user_id,
+                &event.actor_id,
+                &event.detail,
+                &event.ip,
+                &event.user_agent,
+                &event.request_id,
+            ],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Returns the newest events about the user first
+pub async fn read_user_audit_events(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<AuditEntry>, AppError> {
+    let rows = db_conn
+        .query(
+            &format!(
+                "select {} from audit_events \
+                 left join users on users.id = audit_events.user_id \
+                 left join users actors on actors.id = audit_events.actor_id \
+                 where audit_events.user_id=$1 \
+                 order by audit_events.id desc limit $2 offset $3",
+                ENTRY_COLUMNS
+            ),
+            &[&user_id, &limit, &offset],
+        )
+        .await?;
+
+    Ok(rows.into_iter().map(row_to_entry).collect())
+}
+
+/// Returns the newest matching events first. The dates are inclusive.
+pub async fn search_audit_events(
+    db_conn: &PostgresPooledConnection,
+    filter: &AuditFilter,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<AuditEntry>, AppError> {
+    let rows = db_conn
+        .query(
+            &format!(
+                "select {} from audit_events \
+                 left join users on users.id = audit_events.user_id \
+                 left join users actors on actors.id = audit_events.actor_id \
+                 where ($1::varchar is null or audit_events.event = $1) \
+                     and ($2::varchar is null or users.name = $2 or actors.name = $2) \
+                     and ($3::date is null or audit_events.created_at >= $3) \
+                     and ($4::date is null or audit_events.created_at < $4 + 1) \
+                 order by audit_events.id desc limit $5 offset $6",
+                ENTRY_COLUMNS
+            ),
+            &[
+                &filter.event,
+                &filter.user_name,
+                &filter.from,
+                &filter.to,
+                &limit,
+                &offset,
+            ],
+        )
+        .await?;
+
+    Ok(rows.into_iter().map(row_to_entry).collect())
+}
+
+fn row_to_entry(row: Row) -> AuditEntry {
+    AuditEntry {
+        created_at: row.get("created_at"),
+        event: row.get("event"),
+        user_name: row.get("user_name"),
+        actor_name: row.get("actor_name"),
+        detail: row.get("detail"),
+        ip: row.get("ip"),
+        user_agent: row.get("user_agent"),
+        request_id: row.get("request_id"),
+    }
+}
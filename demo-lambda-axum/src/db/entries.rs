@@ -1,62 +1,308 @@
 use crate::db::PostgresPooledConnection;
+use crate::error::AppError;
 use chrono::NaiveDate;
+use serde::Serialize;
+use tokio_postgres::{GenericClient, Row};
 use uuid::Uuid;
 
+#[derive(Serialize)]
 pub struct Entry {
     pub date: NaiveDate,
     pub id: Uuid,
     pub content: String,
+    pub tags: Vec<String>,
 }
 
+/// An entry matching a search, with the matching words of its content highlighted
+#[derive(Serialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub entry: Entry,
+    /// Escaped HTML, the matches wrapped in `<mark>`
+    pub snippet_html: String,
+    pub rank: f32,
+}
+
+pub struct SearchFilter {
+    pub query: String,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+/// Sent around the matches by `ts_headline`. The snippet is escaped before they are replaced
+/// with `<mark>`, and they are removed from the content beforehand, as the API accepts them.
+const HIGHLIGHT_START: char = '\u{2}';
+const HIGHLIGHT_STOP: char = '\u{3}';
+
+const ENTRY_COLUMNS: &str = "entries.date, entries.id, entries.content, \
+    array(select tag from entry_tags where entry_tags.user_id=entries.user_id \
+    and entry_tags.date=entries.date and entry_tags.entry_id=entries.id order by tag) as tags";
+
+/// Returns the entries in the order the user arranged them, and by creation otherwise
 pub async fn read_entries(
-    db_conn: PostgresPooledConnection,
+    db_conn: &PostgresPooledConnection,
     user_id: &Uuid,
     date: &NaiveDate,
-) -> Vec<Entry> {
+) -> Result<Vec<Entry>, AppError> {
     let rows = db_conn
         .query(
-            "select id, content from entries where user_id=$1 and date=$2",
+            &format!(
+                "select {} from entries where user_id=$1 and date=$2 order by position, id",
+                ENTRY_COLUMNS
+            ),
             &[&user_id, &date],
         )
-        .await
-        .unwrap();
+        .await?;
+
+    Ok(rows.into_iter().map(row_to_entry).collect())
+}
+
+/// Returns the entries of every date, oldest date first
+pub async fn read_all_entries(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+) -> Result<Vec<Entry>, AppError> {
+    let rows = db_conn
+        .query(
+            &format!(
+                "select {} from entries where user_id=$1 order by date, position, id",
+                ENTRY_COLUMNS
+            ),
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(rows.into_iter().map(row_to_entry).collect())
+}
+
+/// Returns the entries with the tag across all dates, newest first
+pub async fn read_tagged_entries(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    tag: &String,
+) -> Result<Vec<Entry>, AppError> {
+    let rows = db_conn
+        .query(
+            &format!(
+                "select {} from entries join entry_tags tagged on tagged.user_id=entries.user_id \
+                 and tagged.date=entries.date and tagged.entry_id=entries.id \
+                 where entries.user_id=$1 and tagged.tag=$2 \
+                 order by entries.date desc, entries.id desc",
+                ENTRY_COLUMNS
+            ),
+            &[&user_id, &tag],
+        )
+        .await?;
+
+    Ok(rows.into_iter().map(row_to_entry).collect())
+}
+
+/// Returns the entries matching the query in `websearch_to_tsquery` syntax, the most relevant
+/// first
+pub async fn search_entries(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    filter: &SearchFilter,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<SearchResult>, AppError> {
+    let rows = db_conn
+        .query(
+            &format!(
+                "select {}, \
+                     ts_headline('english', \
+                         translate(entries.content, '\u{2}\u{3}', ''), query, \
+                         'StartSel=\u{2}, StopSel=\u{3}, MaxFragments=2') as snippet, \
+                     ts_rank(entries.search, query) as rank \
+                 from entries, websearch_to_tsquery('english', $2) query \
+                 where entries.user_id=$1 and entries.search @@ query \
+                     and ($3::date is null or entries.date >= $3) \
+                     and ($4::date is null or entries.date <= $4) \
+                 order by rank desc, entries.date desc, entries.id desc limit $5 offset $6",
+                ENTRY_COLUMNS
+            ),
+            &[
+                &user_id,
+                &filter.query,
+                &filter.from,
+                &filter.to,
+                &limit,
+                &offset,
+            ],
+        )
+        .await?;
+
+    Ok(rows.into_iter().map(row_to_search_result).collect())
+}
+
+pub async fn read_entry(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    date: &NaiveDate,
+    id: &Uuid,
+) -> Result<Option<Entry>, AppError> {
+    let row = db_conn
+        .query_opt(
+            &format!(
+                "select {} from entries where user_id=$1 and date=$2 and id=$3",
+                ENTRY_COLUMNS
+            ),
+            &[&user_id, &date, &id],
+        )
+        .await?;
+
+    Ok(row.map(row_to_entry))
+}
+
+/// Adds the entry after the existing ones of the date
+pub async fn create_entry(
+    db_conn: &impl GenericClient,
+    user_id: &Uuid,
+    date: &NaiveDate,
+    id: &Uuid,
+    content: &String,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "insert into entries (user_id, date, id, content, position) values ($1, $2, $3, $4, \
+             (select coalesce(max(position) + 1, 0) from entries where user_id=$1 and date=$2))",
+            &[&user_id, &date, &id, &content],
+        )
+        .await?;
 
-    rows.iter()
-        .map(|row| Entry {
-            date: date.clone(),
-            id: row.get("id"),
-            content: row.get("content"),
-        })
-        .collect()
+    Ok(())
 }
 
+/// Returns `false` if the user has no such entry
 pub async fn update_entry(
-    db_conn: PostgresPooledConnection,
+    db_conn: &impl GenericClient,
     user_id: &Uuid,
     date: &NaiveDate,
     id: &Uuid,
     content: &String,
-) {
+) -> Result<bool, AppError> {
+    let updated = db_conn
+        .execute(
+            "update entries set content=$4 where user_id=$1 and date=$2 and id=$3",
+            &[&user_id, &date, &id, &content],
+        )
+        .await?;
+
+    Ok(updated > 0)
+}
+
+/// Replaces the tags of the entry with `tags`
+pub async fn update_entry_tags(
+    db_conn: &impl GenericClient,
+    user_id: &Uuid,
+    date: &NaiveDate,
+    id: &Uuid,
+    tags: &[String],
+) -> Result<(), AppError> {
     db_conn
         .execute(
-            "insert into entries (user_id, date, id, content) values ($1, $2, $3, $4)",
-            &[&user_id, &date, id, content],
+            "with removed as ( \
+                delete from entry_tags \
+                where user_id=$1 and date=$2 and entry_id=$3 and tag <> all($4::varchar[]) \
+            ) \
+            insert into entry_tags (user_id, date, entry_id, tag) \
+            select $1, $2, $3, tag from unnest($4::varchar[]) as tag \
+            on conflict do nothing",
+            &[&user_id, &date, &id, &tags],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Numbers the entries of the date in the order of `ids`. Nothing is changed and `false` is
+/// returned unless `ids` holds every entry of the user on that date exactly once.
+pub async fn reorder_entries(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    date: &NaiveDate,
+    ids: &[Uuid],
+) -> Result<bool, AppError> {
+    let updated = db_conn
+        .execute(
+            "with ordered as ( \
+                 select id, position from unnest($3::uuid[]) with ordinality as ordered(id, position) \
+             ) \
+             update entries set position = ordered.position - 1 \
+             from ordered \
+             where entries.user_id=$1 and entries.date=$2 and entries.id = ordered.id \
+             and (select array_agg(id order by id) from entries where user_id=$1 and date=$2) \
+                 = (select array_agg(id order by id) from ordered)",
+            &[&user_id, &date, &ids],
         )
-        .await
-        .unwrap();
+        .await?;
+
+    Ok(updated as usize == ids.len())
 }
 
+/// Returns `false` if the user has no such entry
 pub async fn delete_entry(
-    db_conn: PostgresPooledConnection,
+    db_conn: &PostgresPooledConnection,
     user_id: &Uuid,
     date: &NaiveDate,
     id: &Uuid,
-) {
-    db_conn
+) -> Result<bool, AppError> {
+    let deleted = db_conn
         .execute(
             "delete from entries where user_id=$1 and date=$2 and id=$3",
-            &[&user_id, &date, id],
+            &[&user_id, &date, &id],
         )
-        .await
-        .unwrap();
+        .await?;
+
+    Ok(deleted > 0)
+}
+
+fn row_to_entry(row: Row) -> Entry {
+    Entry {
+        date: row.get("date"),
+        id: row.get("id"),
+        content: row.get("content"),
+        tags: row.get("tags"),
+    }
+}
+
+fn row_to_search_result(row: Row) -> SearchResult {
+    let snippet: String = row.get("snippet");
+    let rank = row.get("rank");
+
+    SearchResult {
+        snippet_html: snippet_to_html(&snippet),
+        entry: row_to_entry(row),
+        rank,
+    }
+}
+
+fn snippet_to_html(snippet: &str) -> String {
+    let mut snippet_html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            HIGHLIGHT_START => snippet_html.push_str("<mark>"),
+            HIGHLIGHT_STOP => snippet_html.push_str("</mark>"),
+            '&' => snippet_html.push_str("&amp;"),
+            '<' => snippet_html.push_str("&lt;"),
+            '>' => snippet_html.push_str("&gt;"),
+            '"' => snippet_html.push_str("&quot;"),
+            '\'' => snippet_html.push_str("&#39;"),
+            c => snippet_html.push(c),
+        }
+    }
+    snippet_html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snippet_to_html_marks_the_matches_and_escapes_the_rest() {
+        assert_eq!(
+            snippet_to_html("a \u{2}<b>\u{3} & \"c\"'s"),
+            "a <mark>&lt;b&gt;</mark> &amp; &quot;c&quot;&#39;s"
+        );
+    }
 }
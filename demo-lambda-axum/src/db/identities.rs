@@ -0,0 +1,45 @@
+use crate::db::PostgresPooledConnection;
+use crate::db::users::{USER_COLUMNS, User, row_to_user};
+use crate::error::AppError;
+use uuid::Uuid;
+
+/// Returns the user linked to the identity, and records the login
+pub async fn get_user_by_identity(
+    db_conn: &PostgresPooledConnection,
+    issuer: &str,
+    subject: &str,
+) -> Result<Option<User>, AppError> {
+    let row = db_conn
+        .query_opt(
+            &format!(
+                "with identity as ( \
+                    update user_identities set last_login_at=current_timestamp \
+                    where issuer=$1 and subject=$2 \
+                    returning user_id \
+                ) \
+                select {} from identity join users on users.id=identity.user_id",
+                USER_COLUMNS
+            ),
+            &[&issuer, &subject],
+        )
+        .await?;
+
+    Ok(row.map(row_to_user))
+}
+
+pub async fn link_identity(
+    db_conn: &PostgresPooledConnection,
+    issuer: &str,
+    subject: &str,
+    user_id: &Uuid,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "insert into user_identities (issuer, subject, user_id, last_login_at) \
+             values ($1, $2, $3, current_timestamp)",
+            &[&issuer, &subject, &user_id],
+        )
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use crate::db::PostgresPooledConnection;
+use crate::error::AppError;
+use tokio_postgres::GenericClient;
+use uuid::Uuid;
+
+pub async fn create_invite_code(
+    db_conn: &PostgresPooledConnection,
+    code_hash: &[u8],
+    created_by: Option<&Uuid>,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "insert into invite_codes (code_hash, created_by) values ($1, $2)",
+            &[&code_hash, &created_by],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Consumes the code, returning `false` if it doesn't exist or was already used. Take it in the
+/// transaction of the registration, so that it is put back if the registration fails after all.
+pub async fn take_invite_code(
+    db_conn: &impl GenericClient,
+    code_hash: &[u8],
+) -> Result<bool, AppError> {
+    let deleted = db_conn
+        .execute("delete from invite_codes where code_hash=$1", &[&code_hash])
+        .await?;
+
+    Ok(deleted > 0)
+}
@@ -0,0 +1,66 @@
+use crate::db::PostgresPooledConnection;
+use crate::error::AppError;
+
+/// Returns the number of seconds until the longest lockout among `keys` ends, if any is locked
+pub async fn get_lockout_seconds(
+    db_conn: &PostgresPooledConnection,
+    keys: &[String],
+) -> Result<Option<i32>, AppError> {
+    let row = db_conn
+        .query_one(
+            "select ceil(extract(epoch from max(locked_until) - current_timestamp))::integer as seconds \
+             from login_attempts where key = any($1) and locked_until > current_timestamp",
+            &[&keys],
+        )
+        .await?;
+
+    Ok(row.get("seconds"))
+}
+
+/// Counts a failure and returns the failures so far, forgetting those older than `window_seconds`
+pub async fn record_failure(
+    db_conn: &PostgresPooledConnection,
+    key: &String,
+    window_seconds: i32,
+) -> Result<i32, AppError> {
+    let row = db_conn
+        .query_one(
+            "insert into login_attempts (key, failures, last_failure_at) \
+             values ($1, 1, current_timestamp) \
+             on conflict (key) do update set \
+                 failures = case \
+                     when login_attempts.last_failure_at < current_timestamp - make_interval(secs => $2) then 1 \
+                     else login_attempts.failures + 1 \
+                 end, \
+                 last_failure_at = current_timestamp \
+             returning failures",
+            &[&key, &f64::from(window_seconds)],
+        )
+        .await?;
+
+    Ok(row.get("failures"))
+}
+
+pub async fn lock(
+    db_conn: &PostgresPooledConnection,
+    key: &String,
+    seconds: i32,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "update login_attempts set locked_until = current_timestamp + make_interval(secs => $2) \
+             where key=$1",
+            &[&key, &f64::from(seconds)],
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn reset(db_conn: &PostgresPooledConnection, key: &String) -> Result<(), AppError> {
+    db_conn
+        .execute("delete from login_attempts where key=$1", &[&key])
+        .await?;
+
+    Ok(())
+}
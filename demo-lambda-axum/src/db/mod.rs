@@ -1,4 +1,13 @@
+pub mod access_tokens;
+pub mod audit_events;
 pub mod entries;
+pub mod identities;
+pub mod invite_codes;
+pub mod login_attempts;
+pub mod passkeys;
+pub mod password_resets;
+pub mod sessions;
+pub mod totp;
 pub mod users;
 
 use crate::{AppConfig, AppState};
@@ -33,7 +42,7 @@ pub async fn postgres_pool(config: &AppConfig) -> Result<PostgresPool, BoxError>
         .max_size(1)
         .build(manager)
         .await
-        .map_err(|e| BoxError::from(e))
+        .map_err(BoxError::from)
 }
 
 impl<S> FromRequestParts<S> for DatabaseConnection
@@ -0,0 +1,156 @@
+use crate::db::PostgresPooledConnection;
+use crate::error::AppError;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use tokio_postgres::Row;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::Json;
+use uuid::Uuid;
+use webauthn_rs::prelude::Passkey;
+
+pub struct StoredPasskey {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub passkey: Passkey,
+}
+
+pub async fn read_passkeys(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+) -> Result<Vec<StoredPasskey>, AppError> {
+    let rows = db_conn
+        .query(
+            "select id, name, created_at, last_used_at, passkey from passkeys \
+             where user_id=$1 order by created_at",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(rows.into_iter().map(row_to_passkey).collect())
+}
+
+/// Returns `false` if the credential is already registered
+pub async fn create_passkey(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    name: &String,
+    passkey: &Passkey,
+) -> Result<bool, AppError> {
+    let credential_id: &[u8] = passkey.cred_id().as_ref();
+    let result = db_conn
+        .execute(
+            "insert into passkeys (id, user_id, credential_id, name, passkey) \
+             values ($1, $2, $3, $4, $5)",
+            &[
+                &Uuid::now_v7(),
+                &user_id,
+                &credential_id,
+                &name,
+                &Json(passkey),
+            ],
+        )
+        .await;
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Records a successful authentication, along with the updated signature counter
+pub async fn update_passkey(
+    db_conn: &PostgresPooledConnection,
+    id: &Uuid,
+    passkey: &Passkey,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "update passkeys set passkey=$2, last_used_at=current_timestamp where id=$1",
+            &[&id, &Json(passkey)],
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_passkey(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    id: &Uuid,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "delete from passkeys where user_id=$1 and id=$2",
+            &[&user_id, &id],
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn create_challenge<T: Serialize + Debug + Sync>(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    session_id: Option<&Uuid>,
+    state: &T,
+    ttl_seconds: i32,
+) -> Result<Uuid, AppError> {
+    let id = Uuid::new_v4();
+    db_conn
+        .execute(
+            // abandoned ceremonies are cleaned up along the way
+            "with expired as ( \
+                 delete from webauthn_challenges where expires_at <= current_timestamp \
+             ) \
+             insert into webauthn_challenges (id, user_id, session_id, state, expires_at) \
+             values ($1, $2, $3, $4, current_timestamp + make_interval(secs => $5))",
+            &[
+                &id,
+                &user_id,
+                &session_id,
+                &Json(state),
+                &f64::from(ttl_seconds),
+            ],
+        )
+        .await?;
+
+    Ok(id)
+}
+
+/// Removes the challenge and returns its user and state, unless it expired or belongs to another
+/// session. Taking it makes sure each challenge is answered at most once.
+pub async fn take_challenge<T: DeserializeOwned>(
+    db_conn: &PostgresPooledConnection,
+    id: &Uuid,
+    session_id: Option<&Uuid>,
+) -> Result<Option<(Uuid, T)>, AppError> {
+    let row = db_conn
+        .query_opt(
+            "delete from webauthn_challenges \
+             where id=$1 and session_id is not distinct from $2 and expires_at > current_timestamp \
+             returning user_id, state",
+            &[&id, &session_id],
+        )
+        .await?;
+
+    Ok(row.map(|row| {
+        let Json(state) = row.get("state");
+        (row.get("user_id"), state)
+    }))
+}
+
+fn row_to_passkey(row: Row) -> StoredPasskey {
+    let Json(passkey) = row.get("passkey");
+    StoredPasskey {
+        id: row.get("id"),
+        name: row.get("name"),
+        created_at: row.get("created_at"),
+        last_used_at: row.get("last_used_at"),
+        passkey,
+    }
+}
@@ -0,0 +1,58 @@
+use crate::db::PostgresPooledConnection;
+use crate::error::AppError;
+use uuid::Uuid;
+
+/// Stores a new token, replacing any earlier one of the user and cleaning up expired ones
+pub async fn create_reset_token(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    token_hash: &[u8],
+    ttl_minutes: i32,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "with expired as ( \
+                delete from password_reset_tokens \
+                where user_id=$1 or expires_at < current_timestamp \
+            ) \
+            insert into password_reset_tokens (token_hash, user_id, expires_at) \
+            values ($2, $1, current_timestamp + make_interval(mins => $3))",
+            &[&user_id, &token_hash, &ttl_minutes],
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn is_valid_reset_token(
+    db_conn: &PostgresPooledConnection,
+    token_hash: &[u8],
+) -> Result<bool, AppError> {
+    let row = db_conn
+        .query_opt(
+            "select 1 from password_reset_tokens \
+             where token_hash=$1 and expires_at > current_timestamp",
+            &[&token_hash],
+        )
+        .await?;
+
+    Ok(row.is_some())
+}
+
+/// Consumes the token, returning its user if it was still valid
+pub async fn take_reset_token(
+    db_conn: &PostgresPooledConnection,
+    token_hash: &[u8],
+) -> Result<Option<Uuid>, AppError> {
+    let row = db_conn
+        .query_opt(
+            "delete from password_reset_tokens where token_hash=$1 \
+             returning user_id, expires_at > current_timestamp as valid",
+            &[&token_hash],
+        )
+        .await?;
+
+    Ok(row
+        .filter(|row| row.get::<_, bool>("valid"))
+        .map(|row| row.get("user_id")))
+}
@@ -0,0 +1,246 @@
+use crate::db::PostgresPooledConnection;
+use crate::db::users::{USER_COLUMNS, User, row_to_user};
+use crate::error::AppError;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+pub struct ActiveSession {
+    pub user: User,
+    /// The password was verified, but the second factor is still missing
+    pub mfa_pending: bool,
+    pub remember: bool,
+    /// Seconds until the session expires, after sliding it forward
+    pub ttl_seconds: i32,
+}
+
+/// A session as listed to its user
+#[derive(Serialize)]
+pub struct SessionSummary {
+    /// Also the value of the session cookie, so it is left out of exports
+    #[serde(skip)]
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// How long a session lasts without activity, and at most
+pub struct SessionLifetime {
+    pub idle_minutes: i32,
+    pub absolute_minutes: i32,
+}
+
+pub async fn create_session(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    lifetime: &SessionLifetime,
+    mfa_pending: bool,
+    remember: bool,
+    user_agent: Option<&str>,
+    ip: Option<&str>,
+) -> Result<Uuid, AppError> {
+    let id = Uuid::new_v4();
+    db_conn
+        .execute(
+            "insert into sessions (id, user_id, idle_minutes, expires_at, absolute_expires_at, \
+                 mfa_pending, remember, user_agent, ip) \
+             values ($1, $2, $3, current_timestamp + make_interval(mins => least($3, $4)), \
+                 current_timestamp + make_interval(mins => $4), $5, $6, $7, $8)",
+            &[
+                &id,
+                &user_id,
+                &lifetime.idle_minutes,
+                &lifetime.absolute_minutes,
+                &mfa_pending,
+                &remember,
+                &user_agent,
+                &ip,
+            ],
+        )
+        .await?;
+
+    Ok(id)
+}
+
+/// Marks the session as seen, slides its expiry forward and returns it, if it is neither expired
+/// nor revoked
+pub async fn touch_active_session(
+    db_conn: &PostgresPooledConnection,
+    id: &Uuid,
+) -> Result<Option<ActiveSession>, AppError> {
+    let row = db_conn
+        .query_opt(
+            &format!(
+                "with session as ( \
+                     update sessions set last_seen_at=current_timestamp, \
+                         expires_at=least( \
+                             current_timestamp + make_interval(mins => idle_minutes), \
+                             absolute_expires_at \
+                         ) \
+                     where id=$1 and revoked_at is null and expires_at > current_timestamp \
+                     returning user_id, mfa_pending, remember, \
+                         extract(epoch from expires_at - current_timestamp)::integer \
+                             as ttl_seconds \
+                 ) \
+                 select {}, session.mfa_pending, session.remember, session.ttl_seconds \
+                 from users join session on users.id = session.user_id",
+                USER_COLUMNS
+            ),
+            &[&id],
+        )
+        .await?;
+
+    Ok(row.map(|row| ActiveSession {
+        mfa_pending: row.get("mfa_pending"),
+        remember: row.get("remember"),
+        ttl_seconds: row.get("ttl_seconds"),
+        user: row_to_user(row),
+    }))
+}
+
+/// Returns every session of the user, including expired and revoked ones, newest first
+pub async fn read_user_sessions(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+) -> Result<Vec<SessionSummary>, AppError> {
+    let rows = db_conn
+        .query(
+            "select id, created_at, last_seen_at, expires_at, revoked_at, user_agent, ip \
+             from sessions where user_id=$1 and not mfa_pending order by created_at desc",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(rows.into_iter().map(row_to_session_summary).collect())
+}
+
+/// Returns the sessions of the user that are neither expired nor revoked, most recently used first
+pub async fn read_active_sessions(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+) -> Result<Vec<SessionSummary>, AppError> {
+    let rows = db_conn
+        .query(
+            "select id, created_at, last_seen_at, expires_at, revoked_at, user_agent, ip \
+             from sessions where user_id=$1 and not mfa_pending and revoked_at is null \
+                 and expires_at > current_timestamp \
+             order by last_seen_at desc",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(rows.into_iter().map(row_to_session_summary).collect())
+}
+
+pub async fn revoke_session(db_conn: &PostgresPooledConnection, id: &Uuid) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "update sessions set revoked_at=current_timestamp where id=$1 and revoked_at is null",
+            &[&id],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Revokes a session of the user, returns `false` if there is no such session
+pub async fn revoke_user_session(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    id: &Uuid,
+) -> Result<bool, AppError> {
+    let revoked = db_conn
+        .execute(
+            "update sessions set revoked_at=current_timestamp \
+             where id=$1 and user_id=$2 and revoked_at is null",
+            &[&id, &user_id],
+        )
+        .await?;
+
+    Ok(revoked > 0)
+}
+
+/// Revokes every session of the user except the given one
+pub async fn revoke_other_sessions(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    id: &Uuid,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "update sessions set revoked_at=current_timestamp \
+             where user_id=$1 and id<>$2 and revoked_at is null",
+            &[&user_id, &id],
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn revoke_user_sessions(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "update sessions set revoked_at=current_timestamp where user_id=$1 and revoked_at is null",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Records that the user confirmed their identity, returns `false` if the session isn't theirs or
+/// is no longer active
+pub async fn mark_reauthenticated(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    id: &Uuid,
+) -> Result<bool, AppError> {
+    let updated = db_conn
+        .execute(
+            "update sessions set reauthenticated_at=current_timestamp \
+             where id=$1 and user_id=$2 and revoked_at is null and expires_at > current_timestamp",
+            &[&id, &user_id],
+        )
+        .await?;
+
+    Ok(updated > 0)
+}
+
+/// Whether the user of the session confirmed their identity within the last `minutes`
+pub async fn is_reauthenticated(
+    db_conn: &PostgresPooledConnection,
+    id: &Uuid,
+    minutes: i32,
+) -> Result<bool, AppError> {
+    let row = db_conn
+        .query_one(
+            "select exists ( \
+                 select 1 from sessions where id=$1 \
+                 and reauthenticated_at > current_timestamp - make_interval(mins => $2) \
+             ) as reauthenticated",
+            &[&id, &minutes],
+        )
+        .await?;
+
+    Ok(row.get("reauthenticated"))
+}
+
+fn row_to_session_summary(row: Row) -> SessionSummary {
+    SessionSummary {
+        id: row.get("id"),
+        created_at: row.get("created_at"),
+        last_seen_at: row.get("last_seen_at"),
+        expires_at: row.get("expires_at"),
+        revoked_at: row.get("revoked_at"),
+        user_agent: row.get("user_agent"),
+        ip: row.get("ip"),
+    }
+}
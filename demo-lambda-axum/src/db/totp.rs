@@ -0,0 +1,102 @@
+use crate::db::PostgresPooledConnection;
+use crate::error::AppError;
+use uuid::Uuid;
+
+/// Stores a freshly generated secret that still has to be confirmed with a first code
+pub async fn set_pending_secret(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    encrypted_secret: &[u8],
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "update users set totp_secret=$2, totp_last_step=null \
+             where id=$1 and totp_enabled_at is null",
+            &[&user_id, &encrypted_secret],
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_secret(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+) -> Result<Option<Vec<u8>>, AppError> {
+    let row = db_conn
+        .query_one("select totp_secret from users where id=$1", &[&user_id])
+        .await?;
+
+    Ok(row.get("totp_secret"))
+}
+
+/// Records the time step of an accepted code. Returns `false` if that step or a later one was
+/// already used, i.e. the code is replayed.
+pub async fn accept_step(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    step: i64,
+) -> Result<bool, AppError> {
+    let updated = db_conn
+        .execute(
+            "update users set totp_last_step=$2 \
+             where id=$1 and (totp_last_step is null or totp_last_step < $2)",
+            &[&user_id, &step],
+        )
+        .await?;
+
+    Ok(updated == 1)
+}
+
+/// Enables the pending secret and replaces any previous recovery codes
+pub async fn enable(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    recovery_code_hashes: &[Vec<u8>],
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "with enabled as ( \
+                 update users set totp_enabled_at=current_timestamp where id=$1 \
+             ), deleted as ( \
+                 delete from recovery_codes where user_id=$1 \
+             ) \
+             insert into recovery_codes (user_id, code_hash) select $1, unnest($2::bytea[])",
+            &[&user_id, &recovery_code_hashes],
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn disable(db_conn: &PostgresPooledConnection, user_id: &Uuid) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "with disabled as ( \
+                 update users set totp_secret=null, totp_enabled_at=null, totp_last_step=null \
+                 where id=$1 \
+             ) \
+             delete from recovery_codes where user_id=$1",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Returns whether an unused recovery code matched, marking it as used
+pub async fn use_recovery_code(
+    db_conn: &PostgresPooledConnection,
+    user_id: &Uuid,
+    code_hash: &[u8],
+) -> Result<bool, AppError> {
+    let updated = db_conn
+        .execute(
+            "update recovery_codes set used_at=current_timestamp \
+             where user_id=$1 and code_hash=$2 and used_at is null",
+            &[&user_id, &code_hash],
+        )
+        .await?;
+
+    Ok(updated == 1)
+}
@@ -1,38 +1,293 @@
 use crate::db::PostgresPooledConnection;
-use tokio_postgres::Row;
+use crate::error::AppError;
+use chrono::NaiveDateTime;
+use std::fmt;
+use std::str::FromStr;
+use strum::{Display, EnumString, IntoStaticStr};
+use tokio_postgres::error::SqlState;
+use tokio_postgres::{GenericClient, Row};
 use uuid::Uuid;
 
+#[derive(Clone, Copy, Debug, Display, EnumString, IntoStaticStr, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+#[derive(Clone)]
 pub struct User {
     pub id: Uuid,
     pub name: String,
+    /// PHC-formatted Argon2 hash, or plaintext for rows created before hashing was introduced
     pub password: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub totp_enabled: bool,
+    pub role: Role,
+    /// Disabled users can't log in, and their sessions and tokens are rejected
+    pub disabled: bool,
+    /// Set while the account waits for its deletion, which logging in can still cancel
+    pub delete_after: Option<NaiveDateTime>,
+}
+
+/// A row of the admin user list
+pub struct UserSummary {
+    pub id: Uuid,
+    pub name: String,
+    pub email: Option<String>,
+    pub role: Role,
+    pub disabled: bool,
+    pub entry_count: i64,
+    pub last_login_at: Option<NaiveDateTime>,
 }
 
-pub async fn get_user_by_id(db_conn: PostgresPooledConnection, id: &Uuid) -> Option<User> {
+/// Columns read by [`row_to_user`], qualified so they can be selected from joins
+pub const USER_COLUMNS: &str = "users.id, users.name, users.password, users.email, \
+     users.email_verified_at is not null as email_verified, \
+     users.totp_enabled_at is not null as totp_enabled, users.role, \
+     users.disabled_at is not null as disabled, users.delete_after";
+
+pub async fn get_user_by_id(
+    db_conn: &PostgresPooledConnection,
+    id: &Uuid,
+) -> Result<Option<User>, AppError> {
     let row = db_conn
-        .query_opt("select id, name, password from users where id=$1", &[&id])
-        .await
-        .unwrap();
+        .query_opt(
+            &format!("select {} from users where id=$1", USER_COLUMNS),
+            &[&id],
+        )
+        .await?;
 
-    row.map(row_to_user)
+    Ok(row.map(row_to_user))
 }
 
-pub async fn get_user_by_name(db_conn: PostgresPooledConnection, name: &String) -> Option<User> {
+pub async fn get_user_by_name(
+    db_conn: &PostgresPooledConnection,
+    name: &String,
+) -> Result<Option<User>, AppError> {
     let row = db_conn
         .query_opt(
-            "select id, name, password from users where name=$1",
+            &format!("select {} from users where name=$1", USER_COLUMNS),
             &[&name],
         )
-        .await
-        .unwrap();
+        .await?;
+
+    Ok(row.map(row_to_user))
+}
+
+/// Emails are matched case-insensitively, like the unique index on them
+pub async fn get_user_by_email(
+    db_conn: &PostgresPooledConnection,
+    email: &str,
+) -> Result<Option<User>, AppError> {
+    let row = db_conn
+        .query_opt(
+            &format!(
+                "select {} from users where lower(email)=lower($1)",
+                USER_COLUMNS
+            ),
+            &[&email],
+        )
+        .await?;
+
+    Ok(row.map(row_to_user))
+}
+
+/// Returns `None` if the name or email is already taken. In a transaction the conflict aborts it,
+/// so it has to be rolled back.
+pub async fn create_user(
+    db_conn: &impl GenericClient,
+    name: &String,
+    email: Option<&str>,
+    password_hash: &String,
+) -> Result<Option<User>, AppError> {
+    let id = Uuid::now_v7();
+    let result = db_conn
+        .query_one(
+            &format!(
+                "insert into users (id, name, email, password) values ($1, $2, $3, $4) \
+                 returning {}",
+                USER_COLUMNS
+            ),
+            &[&id, &name, &email, &password_hash],
+        )
+        .await;
+
+    match result {
+        Ok(row) => Ok(Some(row_to_user(row))),
+        Err(e) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn update_password(
+    db_conn: &PostgresPooledConnection,
+    id: &Uuid,
+    password_hash: &String,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "update users set password=$2 where id=$1",
+            &[&id, &password_hash],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Replaces the address, which then has to be verified again. Returns `false` if it is taken.
+pub async fn update_email(
+    db_conn: &PostgresPooledConnection,
+    id: &Uuid,
+    email: &str,
+) -> Result<bool, AppError> {
+    let result = db_conn
+        .execute(
+            "update users set email=$2, email_verified_at=null where id=$1",
+            &[&id, &email],
+        )
+        .await;
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns `false` if the name is taken by another user
+pub async fn update_name(
+    db_conn: &PostgresPooledConnection,
+    id: &Uuid,
+    name: &str,
+) -> Result<bool, AppError> {
+    let result = db_conn
+        .execute("update users set name=$2 where id=$1", &[&id, &name])
+        .await;
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Only marks the address if it is still the one the verification link was sent to
+pub async fn mark_email_verified(
+    db_conn: &PostgresPooledConnection,
+    id: &Uuid,
+    email: &str,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "update users set email_verified_at=current_timestamp \
+             where id=$1 and lower(email)=lower($2) and email_verified_at is null",
+            &[&id, &email],
+        )
+        .await?;
 
-    row.map(row_to_user)
+    Ok(())
 }
 
-fn row_to_user(row: Row) -> User {
+/// Last login is the start of the latest session that got past the second factor
+pub async fn list_users(db_conn: &PostgresPooledConnection) -> Result<Vec<UserSummary>, AppError> {
+    let rows = db_conn
+        .query(
+            "select users.id, users.name, users.email, users.role, \
+                 users.disabled_at is not null as disabled, \
+                 (select count(*) from entries where entries.user_id=users.id) as entry_count, \
+                 (select max(created_at) from sessions \
+                  where sessions.user_id=users.id and not sessions.mfa_pending) as last_login_at \
+             from users order by users.name",
+            &[],
+        )
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| UserSummary {
+            id: row.get("id"),
+            name: row.get("name"),
+            email: row.get("email"),
+            role: row_to_role(&row),
+            disabled: row.get("disabled"),
+            entry_count: row.get("entry_count"),
+            last_login_at: row.get("last_login_at"),
+        })
+        .collect())
+}
+
+pub async fn set_disabled(
+    db_conn: &PostgresPooledConnection,
+    id: &Uuid,
+    disabled: bool,
+) -> Result<(), AppError> {
+    db_conn
+        .execute(
+            "update users set disabled_at=case when $2 then current_timestamp end where id=$1",
+            &[&id, &disabled],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Schedules the account for deletion by the purge task once the grace period has passed, and
+/// returns when that will be
+pub async fn schedule_deletion(
+    db_conn: &PostgresPooledConnection,
+    id: &Uuid,
+    grace_days: i32,
+) -> Result<NaiveDateTime, AppError> {
+    let row = db_conn
+        .query_one(
+            "update users set delete_after=current_timestamp + make_interval(days => $2) \
+             where id=$1 returning delete_after",
+            &[&id, &grace_days],
+        )
+        .await?;
+
+    Ok(row.get("delete_after"))
+}
+
+pub async fn cancel_deletion(
+    db_conn: &PostgresPooledConnection,
+    id: &Uuid,
+) -> Result<(), AppError> {
+    db_conn
+        .execute("update users set delete_after=null where id=$1", &[&id])
+        .await?;
+
+    Ok(())
+}
+
+pub fn row_to_user(row: Row) -> User {
     User {
         id: row.get("id"),
         name: row.get("name"),
         password: row.get("password"),
+        email: row.get("email"),
+        email_verified: row.get("email_verified"),
+        totp_enabled: row.get("totp_enabled"),
+        role: row_to_role(&row),
+        disabled: row.get("disabled"),
+        delete_after: row.get("delete_after"),
     }
 }
+
+impl fmt::Debug for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // keep the password hash out of traces
+        f.debug_struct("User")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("role", &self.role)
+            .finish()
+    }
+}
+
+fn row_to_role(row: &Row) -> Role {
+    // the column is constrained to known roles, fall back to the least privileged one anyway
+    Role::from_str(row.get("role")).unwrap_or(Role::User)
+}
@@ -1,16 +1,20 @@
 use std::error::Error;
 use std::panic::Location;
 
+use argon2::password_hash::Error as PasswordHashError;
 use askama::Error as AskamaError;
 use axum::Json;
-use axum::extract::rejection::FormRejection;
+use axum::extract::rejection::{FormRejection, JsonRejection};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use bb8::RunError;
 use serde::Serialize;
 use strum::IntoStaticStr;
 use thiserror::Error;
+use tokio_postgres::Error as PostgresError;
 use util::tracing;
 use validator::ValidationErrors;
+use webauthn_rs::prelude::WebauthnError;
 
 #[derive(Debug, Serialize)]
 pub struct ErrorResp {
@@ -48,6 +52,85 @@ pub enum AppError {
         location: &'static Location<'static>,
         source: FormRejection,
     },
+
+    #[error("JSON rejection: {}", source)]
+    AxumJsonRejection {
+        location: &'static Location<'static>,
+        source: JsonRejection,
+    },
+
+    #[error("CSRF token missing or invalid")]
+    CsrfError {
+        location: &'static Location<'static>,
+    },
+
+    #[error("Access token missing or invalid")]
+    UnauthorizedError {
+        location: &'static Location<'static>,
+    },
+
+    #[error("Access token lacks the {} scope", scope)]
+    InsufficientScope {
+        scope: &'static str,
+        location: &'static Location<'static>,
+    },
+
+    #[error("Access denied")]
+    Forbidden {
+        location: &'static Location<'static>,
+    },
+
+    #[error("Not found")]
+    NotFound {
+        location: &'static Location<'static>,
+    },
+
+    #[error("A verified email address is required")]
+    EmailNotVerified {
+        location: &'static Location<'static>,
+    },
+
+    #[error("Database error: {}", source)]
+    DatabaseError {
+        location: &'static Location<'static>,
+        source: PostgresError,
+    },
+
+    #[error("Database pool error: {}", source)]
+    DatabasePoolError {
+        location: &'static Location<'static>,
+        source: RunError<PostgresError>,
+    },
+
+    #[error("TOTP error: {}", message)]
+    TotpError {
+        message: String,
+        location: &'static Location<'static>,
+    },
+
+    #[error("Passkey error: {}", message)]
+    PasskeyError {
+        message: String,
+        location: &'static Location<'static>,
+    },
+
+    #[error("Mail error: {}", message)]
+    MailError {
+        message: String,
+        location: &'static Location<'static>,
+    },
+
+    #[error("OpenID Connect error: {}", message)]
+    OidcError {
+        message: String,
+        location: &'static Location<'static>,
+    },
+
+    #[error("Password hash error: {}", source)]
+    PasswordHashError {
+        location: &'static Location<'static>,
+        source: PasswordHashError,
+    },
 }
 
 impl AppError {
@@ -61,6 +144,26 @@ impl AppError {
             }
             AppError::ValidationError { location, .. } => (StatusCode::BAD_REQUEST, location),
             AppError::AxumFormRejection { location, .. } => (StatusCode::BAD_REQUEST, location),
+            AppError::AxumJsonRejection { location, .. } => (StatusCode::BAD_REQUEST, location),
+            AppError::CsrfError { location } => (StatusCode::FORBIDDEN, location),
+            AppError::UnauthorizedError { location } => (StatusCode::UNAUTHORIZED, location),
+            AppError::InsufficientScope { location, .. } => (StatusCode::FORBIDDEN, location),
+            AppError::Forbidden { location } => (StatusCode::FORBIDDEN, location),
+            AppError::NotFound { location } => (StatusCode::NOT_FOUND, location),
+            AppError::EmailNotVerified { location } => (StatusCode::FORBIDDEN, location),
+            AppError::DatabaseError { location, .. } => {
+                (StatusCode::INTERNAL_SERVER_ERROR, location)
+            }
+            AppError::DatabasePoolError { location, .. } => {
+                (StatusCode::INTERNAL_SERVER_ERROR, location)
+            }
+            AppError::TotpError { location, .. } => (StatusCode::INTERNAL_SERVER_ERROR, location),
+            AppError::PasskeyError { location, .. } => (StatusCode::BAD_REQUEST, location),
+            AppError::MailError { location, .. } => (StatusCode::INTERNAL_SERVER_ERROR, location),
+            AppError::OidcError { location, .. } => (StatusCode::BAD_GATEWAY, location),
+            AppError::PasswordHashError { location, .. } => {
+                (StatusCode::INTERNAL_SERVER_ERROR, location)
+            }
         };
 
         tracing::error!(
@@ -97,6 +200,81 @@ pub fn sample_error(message: String) -> AppError {
     }
 }
 
+#[track_caller]
+pub fn csrf_error() -> AppError {
+    AppError::CsrfError {
+        location: Location::caller(),
+    }
+}
+
+#[track_caller]
+pub fn unauthorized_error() -> AppError {
+    AppError::UnauthorizedError {
+        location: Location::caller(),
+    }
+}
+
+#[track_caller]
+pub fn insufficient_scope(scope: &'static str) -> AppError {
+    AppError::InsufficientScope {
+        scope,
+        location: Location::caller(),
+    }
+}
+
+#[track_caller]
+pub fn forbidden_error() -> AppError {
+    AppError::Forbidden {
+        location: Location::caller(),
+    }
+}
+
+#[track_caller]
+pub fn not_found_error() -> AppError {
+    AppError::NotFound {
+        location: Location::caller(),
+    }
+}
+
+#[track_caller]
+pub fn email_not_verified() -> AppError {
+    AppError::EmailNotVerified {
+        location: Location::caller(),
+    }
+}
+
+#[track_caller]
+pub fn totp_error(message: String) -> AppError {
+    AppError::TotpError {
+        message,
+        location: Location::caller(),
+    }
+}
+
+#[track_caller]
+pub fn passkey_error(message: String) -> AppError {
+    AppError::PasskeyError {
+        message,
+        location: Location::caller(),
+    }
+}
+
+#[track_caller]
+pub fn mail_error(message: String) -> AppError {
+    AppError::MailError {
+        message,
+        location: Location::caller(),
+    }
+}
+
+#[track_caller]
+pub fn oidc_error(message: String) -> AppError {
+    AppError::OidcError {
+        message,
+        location: Location::caller(),
+    }
+}
+
 impl From<AskamaError> for AppError {
     #[track_caller]
     fn from(value: AskamaError) -> Self {
@@ -126,3 +304,63 @@ impl From<FormRejection> for AppError {
         }
     }
 }
+
+impl From<PostgresError> for AppError {
+    #[track_caller]
+    fn from(value: PostgresError) -> Self {
+        AppError::DatabaseError {
+            location: Location::caller(),
+            source: value,
+        }
+    }
+}
+
+impl From<RunError<PostgresError>> for AppError {
+    #[track_caller]
+    fn from(value: RunError<PostgresError>) -> Self {
+        AppError::DatabasePoolError {
+            location: Location::caller(),
+            source: value,
+        }
+    }
+}
+
+impl From<PasswordHashError> for AppError {
+    #[track_caller]
+    fn from(value: PasswordHashError) -> Self {
+        AppError::PasswordHashError {
+            location: Location::caller(),
+            source: value,
+        }
+    }
+}
+
+impl From<argon2::Error> for AppError {
+    #[track_caller]
+    fn from(value: argon2::Error) -> Self {
+        AppError::PasswordHashError {
+            location: Location::caller(),
+            source: value.into(),
+        }
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    #[track_caller]
+    fn from(value: JsonRejection) -> Self {
+        AppError::AxumJsonRejection {
+            location: Location::caller(),
+            source: value,
+        }
+    }
+}
+
+impl From<WebauthnError> for AppError {
+    #[track_caller]
+    fn from(value: WebauthnError) -> Self {
+        AppError::PasskeyError {
+            message: value.to_string(),
+            location: Location::caller(),
+        }
+    }
+}
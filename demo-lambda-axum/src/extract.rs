@@ -1,9 +1,16 @@
 use crate::error::AppError;
-use axum::Form;
-use axum::extract::rejection::FormRejection;
-use axum::extract::{FromRequest, Request};
+use axum::extract::rejection::{FormRejection, JsonRejection};
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::header::USER_AGENT;
+use axum::http::request::Parts;
+use axum::{Form, Json};
+use lambda_http::RequestExt;
+use lambda_http::request::RequestContext;
 use serde::de::DeserializeOwned;
-use validator::Validate;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::IpAddr;
+use validator::{Validate, ValidationErrors};
 
 pub struct ValidatedForm<T>(pub T);
 
@@ -21,3 +28,155 @@ where
         Ok(ValidatedForm(value))
     }
 }
+
+impl<T> ValidatedForm<T> {
+    /// Separates failed validations from other rejections, for handlers that take
+    /// `Result<ValidatedForm<T>, AppError>` to render the messages next to their inputs
+    pub fn or_field_errors(
+        form: Result<Self, AppError>,
+    ) -> Result<Result<T, FieldErrors>, AppError> {
+        match form {
+            Ok(ValidatedForm(value)) => Ok(Ok(value)),
+            Err(AppError::ValidationError { source, .. }) => Ok(Err(FieldErrors::from(&source))),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// The first message of each invalid form field
+#[derive(Debug, Default)]
+pub struct FieldErrors(HashMap<String, String>);
+
+impl FieldErrors {
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.0.get(field).map(String::as_str)
+    }
+
+    /// Adds a failure found after validation, e.g. a wrong password
+    pub fn with(mut self, field: &str, message: &str) -> Self {
+        self.0.insert(field.to_owned(), message.to_owned());
+        self
+    }
+}
+
+impl From<&ValidationErrors> for FieldErrors {
+    fn from(errors: &ValidationErrors) -> Self {
+        let messages = errors
+            .field_errors()
+            .into_iter()
+            .map(|(field, errors)| {
+                let message = errors
+                    .iter()
+                    .find_map(|error| error.message.as_deref())
+                    .unwrap_or("Is invalid");
+                (field.to_string(), message.to_owned())
+            })
+            .collect();
+        FieldErrors(messages)
+    }
+}
+
+pub struct ValidatedJson<T>(pub T);
+
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+    Json<T>: FromRequest<S, Rejection = JsonRejection>,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        value.validate()?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+/// Source IP and user agent of the request, as reported by the Lambda request context
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// The AWS request id of the invocation, to find its logs
+    pub request_id: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn from_parts(parts: &Parts) -> Self {
+        let ip = match parts.extensions.request_context_ref() {
+            Some(RequestContext::ApiGatewayV2(context)) => context.http.source_ip.clone(),
+            Some(RequestContext::ApiGatewayV1(context)) => context.identity.source_ip.clone(),
+            _ => None,
+        }
+        .or_else(|| {
+            // ALB contexts don't carry the source IP, the load balancer forwards it instead. It
+            // appends the address it saw, everything before that comes from the client.
+            parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').next())
+                .and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+                .map(|ip| ip.to_string())
+        });
+
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        let request_id = parts
+            .extensions
+            .lambda_context_ref()
+            .map(|context| context.request_id.clone());
+
+        ClientInfo {
+            ip,
+            user_agent,
+            request_id,
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientInfo::from_parts(parts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    fn client_info(forwarded_for: &str) -> ClientInfo {
+        let (parts, _) = Request::builder()
+            .header("x-forwarded-for", forwarded_for)
+            .body(())
+            .unwrap()
+            .into_parts();
+        ClientInfo::from_parts(&parts)
+    }
+
+    #[test]
+    fn forwarded_for_takes_the_hop_the_load_balancer_appended() {
+        let forged = format!("6.6.6.6, {}, 203.0.113.7", "x".repeat(100));
+        assert_eq!(client_info(&forged).ip.as_deref(), Some("203.0.113.7"));
+        assert_eq!(
+            client_info("2001:db8::1").ip.as_deref(),
+            Some("2001:db8::1")
+        );
+    }
+
+    #[test]
+    fn forwarded_for_without_an_address_is_dropped() {
+        assert_eq!(client_info("6.6.6.6, not-an-ip").ip, None);
+        assert_eq!(client_info(&"1".repeat(100)).ip, None);
+    }
+}
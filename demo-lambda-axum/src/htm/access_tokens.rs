@@ -0,0 +1,109 @@
+use crate::access_token::{self, Scope};
+use crate::audit::{self, AuditEvent};
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::access_tokens::AccessToken;
+use crate::db::users::User;
+use crate::db::{DatabaseConnection, PostgresPooledConnection};
+use crate::error::AppError;
+use crate::extract::{ClientInfo, ValidatedForm};
+use crate::htm::{RenderResult, render};
+use crate::session::CurrentUser;
+use askama::Template;
+use axum::Extension;
+use axum::extract::Path;
+use serde::Deserialize;
+use util::tracing::{self, instrument};
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Deserialize, Validate)]
+pub struct AccessTokenForm {
+    #[validate(length(min = 1, max = 64, message = "Must be between 1 and 64 characters"))]
+    name: String,
+
+    #[serde(default)]
+    entries_read: bool,
+
+    #[serde(default)]
+    entries_write: bool,
+}
+
+#[derive(Template)]
+#[template(path = "access_tokens.html")]
+struct Htm<'a> {
+    csrf_token: &'a str,
+    access_tokens: Vec<AccessToken>,
+    /// A token that was just created, shown this one time only
+    created: Option<String>,
+    error: Option<&'a str>,
+}
+
+#[instrument(skip(csrf))]
+pub async fn get_access_tokens(
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> RenderResult {
+    render_access_tokens(&csrf, &db_conn, &user, None, None).await
+}
+
+#[instrument(skip(csrf, client, form))]
+pub async fn post_access_token(
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    ValidatedForm(form): ValidatedForm<AccessTokenForm>,
+) -> RenderResult {
+    let scopes: Vec<String> = [
+        (form.entries_read, Scope::EntriesRead),
+        (form.entries_write, Scope::EntriesWrite),
+    ]
+    .into_iter()
+    .filter(|(selected, _)| *selected)
+    .map(|(_, scope)| scope.to_string())
+    .collect();
+
+    if scopes.is_empty() {
+        let error = Some("Select at least one scope");
+        return render_access_tokens(&csrf, &db_conn, &user, None, error).await;
+    }
+
+    let (token, token_hash) = access_token::generate_access_token();
+    db::access_tokens::create_access_token(&db_conn, &user.id, &form.name, &token_hash, &scopes)
+        .await?;
+    audit::record(&db_conn, &client, AuditEvent::AccessTokenCreated, &user.id).await?;
+    tracing::info!(user = user.name, "Created an access token");
+
+    render_access_tokens(&csrf, &db_conn, &user, Some(token), None).await
+}
+
+#[instrument(skip(client))]
+pub async fn delete_access_token(
+    CurrentUser(user): CurrentUser,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Path(id): Path<Uuid>,
+) -> Result<(), AppError> {
+    db::access_tokens::delete_access_token(&db_conn, &user.id, &id).await?;
+    audit::record(&db_conn, &client, AuditEvent::AccessTokenDeleted, &user.id).await?;
+    tracing::info!(user = user.name, "Revoked an access token");
+    Ok(())
+}
+
+async fn render_access_tokens(
+    csrf: &CsrfToken,
+    db_conn: &PostgresPooledConnection,
+    user: &User,
+    created: Option<String>,
+    error: Option<&str>,
+) -> RenderResult {
+    let template = Htm {
+        csrf_token: &csrf.0,
+        access_tokens: db::access_tokens::read_access_tokens(db_conn, &user.id).await?,
+        created,
+        error,
+    };
+    render(template)
+}
@@ -0,0 +1,109 @@
+use crate::audit::{self, AuditEvent};
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::DatabaseConnection;
+use crate::db::users::UserSummary;
+use crate::error::{self, AppError};
+use crate::extract::ClientInfo;
+use crate::htm::{RenderResult, render};
+use crate::session::CurrentUser;
+use crate::token;
+use askama::Template;
+use axum::Extension;
+use axum::extract::Path;
+use axum::response::Redirect;
+use util::tracing::{self, instrument};
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+struct Htm<'a> {
+    csrf_token: &'a str,
+    current_user_id: Uuid,
+    users: Vec<UserSummary>,
+    /// Shown once right after creating it, only its hash is stored
+    invite_code: Option<String>,
+}
+
+#[instrument(skip(csrf))]
+pub async fn get_admin(
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> RenderResult {
+    let template = Htm {
+        csrf_token: &csrf.0,
+        current_user_id: user.id,
+        users: db::users::list_users(&db_conn).await?,
+        invite_code: None,
+    };
+    render(template)
+}
+
+/// Creates a code for one registration in the `invite_only` mode
+#[instrument(skip(csrf, client))]
+pub async fn post_invite_code(
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> RenderResult {
+    let invite_code = token::generate_token();
+    db::invite_codes::create_invite_code(
+        &db_conn,
+        &token::hash_token(&invite_code),
+        Some(&user.id),
+    )
+    .await?;
+    audit::record(&db_conn, &client, AuditEvent::InviteCodeCreated, &user.id).await?;
+    tracing::info!(admin = user.name, "Created an invite code");
+
+    let template = Htm {
+        csrf_token: &csrf.0,
+        current_user_id: user.id,
+        users: db::users::list_users(&db_conn).await?,
+        invite_code: Some(invite_code),
+    };
+    render(template)
+}
+
+/// Disabling also ends every session of the user
+#[instrument(skip(client))]
+pub async fn post_disable_user(
+    CurrentUser(user): CurrentUser,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Path(id): Path<Uuid>,
+) -> Result<Redirect, AppError> {
+    if id == user.id {
+        // an admin locking themselves out would leave nobody to undo it
+        return Err(error::forbidden_error());
+    }
+
+    db::users::set_disabled(&db_conn, &id, true).await?;
+    db::sessions::revoke_user_sessions(&db_conn, &id).await?;
+    audit::record_admin_action(&db_conn, &client, AuditEvent::UserDisabled, &user.id, &id).await?;
+    tracing::info!(
+        admin = user.name,
+        user_id = id.to_string(),
+        "Disabled a user"
+    );
+    Ok(Redirect::to("/htm/admin"))
+}
+
+#[instrument(skip(client))]
+pub async fn post_enable_user(
+    CurrentUser(user): CurrentUser,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Path(id): Path<Uuid>,
+) -> Result<Redirect, AppError> {
+    db::users::set_disabled(&db_conn, &id, false).await?;
+    audit::record_admin_action(&db_conn, &client, AuditEvent::UserEnabled, &user.id, &id).await?;
+    tracing::info!(
+        admin = user.name,
+        user_id = id.to_string(),
+        "Enabled a user"
+    );
+    Ok(Redirect::to("/htm/admin"))
+}
@@ -0,0 +1,118 @@
+use crate::audit::AuditEvent;
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::DatabaseConnection;
+use crate::db::audit_events::{AuditEntry, AuditFilter};
+use crate::htm::{PAGE_SIZE, Page, RenderResult, page_offset, render};
+use crate::serde_decorators::empty_string_as_none;
+use crate::session::CurrentUser;
+use askama::Template;
+use axum::Extension;
+use axum::extract::Query;
+use chrono::NaiveDate;
+use serde::Deserialize;
+use strum::IntoEnumIterator;
+use util::tracing::{self, instrument};
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    page: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    event: Option<String>,
+
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    user: Option<String>,
+
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    from: Option<NaiveDate>,
+
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    to: Option<NaiveDate>,
+
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    page: Option<i64>,
+}
+
+/// The security history of the current user
+#[instrument(skip(csrf))]
+pub async fn get_activity(
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Query(params): Query<PageParams>,
+) -> RenderResult {
+    #[derive(Template)]
+    #[template(path = "activity.html")]
+    struct Htm<'a> {
+        csrf_token: &'a str,
+        page: Page<AuditEntry>,
+    }
+
+    let (number, offset) = page_offset(params.page);
+    let entries =
+        db::audit_events::read_user_audit_events(&db_conn, &user.id, PAGE_SIZE + 1, offset).await?;
+
+    let template = Htm {
+        csrf_token: &csrf.0,
+        page: Page::new(entries, number),
+    };
+    render(template)
+}
+
+/// Every event, filtered by type, user and date range. Mounted below the admin routes.
+#[instrument(skip(csrf))]
+pub async fn get_admin_audit(
+    Extension(csrf): Extension<CsrfToken>,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Query(params): Query<AuditParams>,
+) -> RenderResult {
+    #[derive(Template)]
+    #[template(path = "admin_audit.html")]
+    struct Htm<'a> {
+        csrf_token: &'a str,
+        events: Vec<&'static str>,
+        filter: AuditFilter,
+        /// The filter as a query string, for the page links
+        filter_query: String,
+        page: Page<AuditEntry>,
+    }
+
+    let filter = AuditFilter {
+        event: params.event,
+        user_name: params.user,
+        from: params.from,
+        to: params.to,
+    };
+
+    let (number, offset) = page_offset(params.page);
+    let entries =
+        db::audit_events::search_audit_events(&db_conn, &filter, PAGE_SIZE + 1, offset).await?;
+
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    if let Some(event) = &filter.event {
+        query.append_pair("event", event);
+    }
+    if let Some(user_name) = &filter.user_name {
+        query.append_pair("user", user_name);
+    }
+    if let Some(from) = &filter.from {
+        query.append_pair("from", &from.to_string());
+    }
+    if let Some(to) = &filter.to {
+        query.append_pair("to", &to.to_string());
+    }
+
+    let template = Htm {
+        csrf_token: &csrf.0,
+        events: AuditEvent::iter().map(Into::into).collect(),
+        filter_query: query.finish(),
+        filter,
+        page: Page::new(entries, number),
+    };
+    render(template)
+}
@@ -0,0 +1,236 @@
+use crate::AppState;
+use crate::audit::{self, AuditEvent};
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::DatabaseConnection;
+use crate::error::AppError;
+use crate::extract::{ClientInfo, ValidatedForm};
+use crate::htm::oidc;
+use crate::htm::settings::is_reauthenticated;
+use crate::htm::{RenderResult, render};
+use crate::mail;
+use crate::password::{self, PasswordVerification};
+use crate::session::CurrentSession;
+use crate::verification::EmailVerifier;
+use askama::Template;
+use axum::Extension;
+use axum::extract::{Query, State};
+use serde::Deserialize;
+use util::tracing::{self, instrument};
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Deserialize, Validate)]
+pub struct EmailForm {
+    #[validate(
+        email(message = "Must be a valid email address"),
+        length(max = 255, message = "Must be at most 255 characters")
+    )]
+    email: String,
+
+    /// Not needed after reauthenticating with the OpenID Connect provider
+    #[serde(default)]
+    password: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyParams {
+    #[serde(default)]
+    token: String,
+}
+
+#[derive(Template)]
+#[template(path = "email.html")]
+struct Htm<'a> {
+    csrf_token: &'a str,
+    email: Option<&'a str>,
+    verified: bool,
+    required: bool,
+    message: Option<String>,
+    error: Option<&'a str>,
+    oidc_provider_name: Option<&'a str>,
+    /// The password field is left out while this is set
+    reauthenticated: bool,
+}
+
+#[derive(Template)]
+#[template(path = "email/verify_email.txt")]
+struct VerifyEmail<'a> {
+    name: &'a str,
+    link: &'a str,
+    ttl_hours: i64,
+}
+
+#[instrument(skip(state, csrf))]
+pub async fn get_email(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    session: CurrentSession,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> RenderResult {
+    let user = &session.user;
+    let template = Htm {
+        csrf_token: &csrf.0,
+        email: user.email.as_deref(),
+        verified: user.email_verified,
+        required: state.config.email_verification.required_for_writing,
+        message: None,
+        error: None,
+        oidc_provider_name: oidc::provider_name(&state),
+        reauthenticated: is_reauthenticated(&db_conn, &session).await?,
+    };
+    render(template)
+}
+
+/// Asks for the password, so that a stolen session can't redirect the password reset emails
+#[instrument(skip(state, csrf, client, form))]
+pub async fn post_email(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    session: CurrentSession,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    ValidatedForm(form): ValidatedForm<EmailForm>,
+) -> RenderResult {
+    let user = &session.user;
+    let required = state.config.email_verification.required_for_writing;
+    let reauthenticated = is_reauthenticated(&db_conn, &session).await?;
+    let rejected = |error| Htm {
+        csrf_token: &csrf.0,
+        email: user.email.as_deref(),
+        verified: user.email_verified,
+        required,
+        message: None,
+        error: Some(error),
+        oidc_provider_name: oidc::provider_name(&state),
+        reauthenticated,
+    };
+
+    if !reauthenticated
+        && let PasswordVerification::Invalid =
+            password::verify_password(&state.config.password_hash, &form.password, &user.password)?
+    {
+        return render(rejected("Invalid password"));
+    }
+
+    if !db::users::update_email(&db_conn, &user.id, &form.email).await? {
+        return render(rejected("That email is already used by another account"));
+    }
+
+    audit::record(&db_conn, &client, AuditEvent::EmailChanged, &user.id).await?;
+    tracing::info!(user = user.name, "Changed email");
+
+    // The address is already changed, so a failed send is reported rather than returned
+    let message = match send_verification_email(&state, &user.id, &user.name, &form.email).await {
+        Ok(()) => format!("We sent a verification link to {}", form.email),
+        Err(e) => {
+            tracing::error!(error = e.to_string(), "Failed to send verification email");
+            format!(
+                "We could not send a verification link to {}, please use resend to try again",
+                form.email
+            )
+        }
+    };
+
+    let template = Htm {
+        csrf_token: &csrf.0,
+        email: Some(&form.email),
+        verified: false,
+        required,
+        message: Some(message),
+        error: None,
+        oidc_provider_name: oidc::provider_name(&state),
+        reauthenticated,
+    };
+    render(template)
+}
+
+#[instrument(skip(state, csrf))]
+pub async fn post_email_resend(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    session: CurrentSession,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> RenderResult {
+    let user = &session.user;
+    let message = match &user.email {
+        Some(email) if !user.email_verified => {
+            send_verification_email(&state, &user.id, &user.name, email).await?;
+            Some(format!("We sent a verification link to {}", email))
+        }
+        _ => None,
+    };
+
+    let template = Htm {
+        csrf_token: &csrf.0,
+        email: user.email.as_deref(),
+        verified: user.email_verified,
+        required: state.config.email_verification.required_for_writing,
+        message,
+        error: None,
+        oidc_provider_name: oidc::provider_name(&state),
+        reauthenticated: is_reauthenticated(&db_conn, &session).await?,
+    };
+    render(template)
+}
+
+/// Public, the signed token identifies the user, so the link also works in another browser
+#[instrument(skip(state, csrf, params))]
+pub async fn get_email_verify(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Query(params): Query<VerifyParams>,
+) -> RenderResult {
+    #[derive(Template)]
+    #[template(path = "email_verified.html")]
+    struct VerifiedHtm<'a> {
+        csrf_token: &'a str,
+        verified: bool,
+    }
+
+    let user = match EmailVerifier::user_id(&params.token) {
+        Some(user_id) => db::users::get_user_by_id(&db_conn, &user_id).await?,
+        None => None,
+    };
+
+    let verified = match user
+        .as_ref()
+        .and_then(|user| Some((user, user.email.as_ref()?)))
+    {
+        Some((user, email)) if state.email_verifier.verify(&params.token, &user.id, email) => {
+            db::users::mark_email_verified(&db_conn, &user.id, email).await?;
+            tracing::info!(user = user.name, "Verified email");
+            true
+        }
+        _ => false,
+    };
+
+    let template = VerifiedHtm {
+        csrf_token: &csrf.0,
+        verified,
+    };
+    render(template)
+}
+
+pub async fn send_verification_email(
+    state: &AppState,
+    user_id: &Uuid,
+    name: &str,
+    email: &str,
+) -> Result<(), AppError> {
+    let ttl_hours = state.config.email_verification.ttl_hours;
+    let token = state.email_verifier.sign(user_id, email, ttl_hours);
+
+    let mail_config = &state.config.mail;
+    let link = format!("{}/htm/email/verify?token={}", mail_config.base_url, token);
+    let body = VerifyEmail {
+        name,
+        link: &link,
+        ttl_hours,
+    }
+    .render()?;
+
+    let message = mail::message(mail_config, email, "Verify your Journal email", body)?;
+    state.mailer.send(message).await
+}
@@ -0,0 +1,7 @@
+use crate::markdown;
+use askama::filters::Safe;
+
+/// Renders entry content written in Markdown, e.g. `{{ entry.content|markdown }}`
+pub fn markdown(content: &str, _: &dyn askama::Values) -> askama::Result<Safe<String>> {
+    Ok(Safe(markdown::to_html(content)))
+}
@@ -1,91 +1,216 @@
+use crate::AppState;
+use crate::csrf::CsrfToken;
 use crate::db;
 use crate::db::DatabaseConnection;
 use crate::db::entries::Entry;
+use crate::error::{self, AppError};
 use crate::extract::ValidatedForm;
-use crate::htm::{RenderResult, render};
+use crate::htm::{RenderResult, filters, render};
+use crate::markdown;
+use crate::serde_decorators::empty_string_as_none;
+use crate::session::CurrentUser;
+use crate::verification;
 use askama::Template;
-use axum::extract::Path;
-use axum::response::IntoResponse;
-use axum_extra::extract::PrivateCookieJar;
+use axum::Extension;
+use axum::extract::{Path, State};
+use axum::response::{Html, IntoResponse};
+use axum_extra::extract::Form;
 use chrono::NaiveDate;
 use serde::Deserialize;
+use util::tags;
 use util::tracing::{self, instrument};
 use uuid::Uuid;
 use validator::Validate;
 
 #[derive(Deserialize, Validate)]
 pub struct EntryForm {
-    id: Option<String>,
+    /// Set when editing an existing entry
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    id: Option<Uuid>,
 
     #[validate(length(min = 1, message = "Can not be empty"))]
     value: String,
 }
 
 #[derive(Deserialize)]
+pub struct PreviewForm {
+    #[serde(default)]
+    value: String,
+
+    /// The state of the preview checkbox, which is only sent when checked
+    #[serde(default)]
+    preview: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OrderForm {
+    /// The ids of the entries of the date, in their new order
+    #[serde(default)]
+    order: Vec<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
 pub struct DateAndId {
     date: NaiveDate,
     id: Uuid,
 }
 
-#[instrument]
-pub async fn get_index(Path(date): Path<NaiveDate>) -> RenderResult {
+#[instrument(skip(state, csrf))]
+pub async fn get_index(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    Path(date): Path<NaiveDate>,
+) -> RenderResult {
     #[derive(Template)]
     #[template(path = "index.html")]
     struct Htm {
+        csrf_token: String,
         date: NaiveDate,
+        /// Writing is blocked until the email is verified
+        read_only: bool,
     }
 
-    let template = Htm { date };
+    let template = Htm {
+        csrf_token: csrf.0,
+        date,
+        read_only: verification::require_verified_email(&state.config.email_verification, &user)
+            .is_err(),
+    };
     render(template)
 }
 
 #[instrument]
 pub async fn get_journal_entries(
-    jar: PrivateCookieJar,
+    CurrentUser(user): CurrentUser,
     DatabaseConnection(db_conn): DatabaseConnection,
     Path(date): Path<NaiveDate>,
 ) -> RenderResult {
     #[derive(Template)]
     #[template(path = "journal/journal_entries.html")]
     struct Htm {
+        date: NaiveDate,
         entries: Vec<Entry>,
     }
 
-    let user_id = Uuid::parse_str(jar.get("user_id").unwrap().value()).unwrap();
-
     let template = Htm {
-        entries: db::entries::read_entries(db_conn, &user_id, &date).await,
+        date,
+        entries: db::entries::read_entries(&db_conn, &user.id, &date).await?,
     };
     render(template)
 }
 
-#[instrument(skip(entry))]
-pub async fn update_journal_entry(
-    jar: PrivateCookieJar,
+/// A single row of the entries table, e.g. to cancel editing it
+#[instrument]
+pub async fn get_journal_entry(
+    CurrentUser(user): CurrentUser,
     DatabaseConnection(db_conn): DatabaseConnection,
+    Path(params): Path<DateAndId>,
+) -> RenderResult {
+    #[derive(Template)]
+    #[template(path = "journal/journal_entry.html")]
+    struct Htm {
+        entry: Entry,
+    }
+
+    let entry = db::entries::read_entry(&db_conn, &user.id, &params.date, &params.id)
+        .await?
+        .ok_or_else(error::not_found_error)?;
+    render(Htm { entry })
+}
+
+/// The row of the entries table as a form, swapped in to edit the entry in place
+#[instrument]
+pub async fn get_journal_entry_edit(
+    CurrentUser(user): CurrentUser,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Path(params): Path<DateAndId>,
+) -> RenderResult {
+    #[derive(Template)]
+    #[template(path = "journal/journal_entry_edit.html")]
+    struct Htm {
+        entry: Entry,
+    }
+
+    let entry = db::entries::read_entry(&db_conn, &user.id, &params.date, &params.id)
+        .await?
+        .ok_or_else(error::not_found_error)?;
+    render(Htm { entry })
+}
+
+/// The entry being written as it will be shown, or nothing to hide the preview again
+#[instrument(skip(form))]
+pub async fn post_preview(
+    CurrentUser(user): CurrentUser,
+    Form(form): Form<PreviewForm>,
+) -> Html<String> {
+    match form.preview {
+        Some(_) => Html(markdown::to_html(&form.value)),
+        None => Html(String::new()),
+    }
+}
+
+/// Creates an entry, or updates it when the form carries the id of an existing one
+#[instrument(skip(state, entry))]
+pub async fn update_journal_entry(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    DatabaseConnection(mut db_conn): DatabaseConnection,
     Path(date): Path<NaiveDate>,
     ValidatedForm(entry): ValidatedForm<EntryForm>,
-) -> impl IntoResponse {
-    let user_id = Uuid::parse_str(jar.get("user_id").unwrap().value()).unwrap();
-
-    let id = entry
-        .id
-        .clone()
-        .map(|str| Uuid::parse_str(&str).unwrap())
-        .unwrap_or_else(|| Uuid::now_v7());
-    let value = &entry.value;
-
-    db::entries::update_entry(db_conn, &user_id, &date, &id, &value).await;
-    [("HX-Trigger", "load-journal-entries")]
+) -> Result<impl IntoResponse, AppError> {
+    verification::require_verified_email(&state.config.email_verification, &user)?;
+
+    // the content and its tags are saved together, so that searches and tag pages agree
+    let transaction = db_conn.transaction().await?;
+    let id = match entry.id {
+        Some(id) => {
+            if !db::entries::update_entry(&transaction, &user.id, &date, &id, &entry.value).await? {
+                return Err(error::not_found_error());
+            }
+            id
+        }
+        None => {
+            let id = Uuid::now_v7();
+            db::entries::create_entry(&transaction, &user.id, &date, &id, &entry.value).await?;
+            id
+        }
+    };
+    let tags = tags::parse_tags(&entry.value);
+    db::entries::update_entry_tags(&transaction, &user.id, &date, &id, &tags).await?;
+    transaction.commit().await?;
+
+    Ok([("HX-Trigger", "load-journal-entries")])
 }
 
-#[instrument(skip(params))]
+/// Saves the order the entries were dragged into
+#[instrument(skip(state))]
+pub async fn reorder_journal_entries(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Path(date): Path<NaiveDate>,
+    Form(form): Form<OrderForm>,
+) -> Result<(), AppError> {
+    verification::require_verified_email(&state.config.email_verification, &user)?;
+
+    if !db::entries::reorder_entries(&db_conn, &user.id, &date, &form.order).await? {
+        return Err(error::not_found_error());
+    }
+    Ok(())
+}
+
+#[instrument(skip(state, params))]
 pub async fn delete_journal_entry(
-    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
     DatabaseConnection(db_conn): DatabaseConnection,
     Path(params): Path<DateAndId>,
-) {
-    let user_id = Uuid::parse_str(jar.get("user_id").unwrap().value()).unwrap();
+) -> Result<(), AppError> {
+    verification::require_verified_email(&state.config.email_verification, &user)?;
 
-    db::entries::delete_entry(db_conn, &user_id, &params.date, &params.id).await;
+    if !db::entries::delete_entry(&db_conn, &user.id, &params.date, &params.id).await? {
+        return Err(error::not_found_error());
+    }
+    Ok(())
 }
@@ -1,63 +1,189 @@
+use crate::AppState;
+use crate::audit;
+use crate::csrf::CsrfToken;
 use crate::db;
-use crate::db::DatabaseConnection;
+use crate::db::users::User;
+use crate::db::{DatabaseConnection, PostgresPooledConnection};
 use crate::error::AppError;
-use crate::extract::ValidatedForm;
+use crate::extract::{ClientInfo, ValidatedForm};
 use crate::htm::{RenderResult, render};
+use crate::password::{self, PasswordVerification};
+use crate::session::{self, CurrentSession};
+use crate::throttle::LoginThrottle;
+use crate::verification;
 use askama::Template;
+use axum::Extension;
+use axum::extract::State;
 use axum::response::{IntoResponse, Redirect, Response};
 use axum_extra::extract::PrivateCookieJar;
-use axum_extra::extract::cookie::Cookie;
 use serde::Deserialize;
-use time::Duration;
 use util::tracing::{self, instrument};
 use validator::Validate;
 
 #[derive(Deserialize, Validate)]
 pub struct LoginForm {
-    #[validate(length(min = 1, message = "Can not be empty"))]
+    /// Capped at the column width rather than the registration rules, which older accounts may
+    /// not meet
+    #[validate(length(min = 1, max = 255, message = "Must be between 1 and 255 characters"))]
     username: String,
 
     #[validate(length(min = 1, message = "Can not be empty"))]
     password: String,
+
+    #[serde(default)]
+    remember_me: bool,
 }
 
-#[instrument]
-pub async fn get_login() -> RenderResult {
-    #[derive(Template)]
-    #[template(path = "login.html")]
-    struct Htm;
+#[derive(Template)]
+#[template(path = "login.html")]
+struct Htm<'a> {
+    csrf_token: &'a str,
+    registration_open: bool,
+    oidc_provider_name: Option<&'a str>,
+    error: Option<String>,
+}
 
-    let template = Htm;
-    render(template)
+#[instrument(skip(state, csrf))]
+pub async fn get_login(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> RenderResult {
+    render_login(&state, &csrf, None)
 }
 
-#[instrument(skip(login))]
+#[instrument(skip(state, csrf, client, login))]
 pub async fn post_login(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
     jar: PrivateCookieJar,
+    client: ClientInfo,
     DatabaseConnection(db_conn): DatabaseConnection,
     ValidatedForm(login): ValidatedForm<LoginForm>,
 ) -> Result<(PrivateCookieJar, Response), AppError> {
-    #[derive(Template)]
-    #[template(path = "login.html")]
-    struct Htm;
-
-    if let Some(user) = db::users::get_user_by_name(db_conn, &login.username).await {
-        // TODO: password should be hashed
-        if login.password == user.password {
-            let cookie = Cookie::build(("user_id", user.id.hyphenated().to_string()))
-                .path("/")
-                .secure(true)
-                .http_only(true)
-                .max_age(Duration::minutes(30));
-
-            let updated_jar = jar.add(cookie);
-            Ok((updated_jar, Redirect::to("/htm/index").into_response()))
-        } else {
-            let template = Htm;
-            render(template).map(|html| (jar, html.into_response()))
+    let throttle = LoginThrottle::new(&state.config.login_throttle, &login.username, &client);
+
+    if let Some(seconds) = throttle.lockout_seconds(&db_conn).await? {
+        tracing::warn!(seconds, "Login attempt while locked out");
+        audit::record_failed_login(&db_conn, &client, &login.username, "locked out").await?;
+        let error = format!(
+            "Too many failed login attempts. Try again in {}.",
+            describe_wait(seconds)
+        );
+        return render_login(&state, &csrf, Some(error)).map(|html| (jar, html.into_response()));
+    }
+
+    let Some(user) = authenticate(&state, &db_conn, &login).await? else {
+        throttle.record_failure(&db_conn).await?;
+        audit::record_failed_login(&db_conn, &client, &login.username, "invalid credentials")
+            .await?;
+        let error = "Invalid username or password".to_owned();
+        return render_login(&state, &csrf, Some(error)).map(|html| (jar, html.into_response()));
+    };
+
+    throttle.record_success(&db_conn).await?;
+
+    if user.disabled {
+        tracing::warn!(user = user.name, "Login attempt of a disabled account");
+        audit::record_failed_login(&db_conn, &client, &login.username, "account disabled").await?;
+        let error = "This account is disabled".to_owned();
+        return render_login(&state, &csrf, Some(error)).map(|html| (jar, html.into_response()));
+    }
+
+    if user.totp_enabled {
+        tracing::info!(
+            user = user.name,
+            "Password verified, waiting for second factor"
+        );
+        let updated_jar =
+            session::start_pending_session(&db_conn, jar, &user, &client, login.remember_me)
+                .await?;
+        return Ok((updated_jar, Redirect::to("/htm/login/totp").into_response()));
+    }
+
+    tracing::info!(
+        user = user.name,
+        email_verified = user.email_verified,
+        "Logged in"
+    );
+    let updated_jar = session::start_session(
+        &db_conn,
+        &state.config.session,
+        jar,
+        &user,
+        &client,
+        login.remember_me,
+    )
+    .await?;
+    let landing = verification::landing_path(&state.config.email_verification, &user);
+    Ok((updated_jar, Redirect::to(landing).into_response()))
+}
+
+#[instrument(skip(client))]
+pub async fn post_logout(
+    jar: PrivateCookieJar,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    session: CurrentSession,
+) -> Result<(PrivateCookieJar, Redirect), AppError> {
+    let updated_jar = session::end_session(&db_conn, jar, &session, &client).await?;
+    tracing::info!(user = session.user.name, "Logged out");
+    Ok((updated_jar, Redirect::to("/htm/login")))
+}
+
+#[instrument(skip(client))]
+pub async fn post_logout_all(
+    jar: PrivateCookieJar,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    session: CurrentSession,
+) -> Result<(PrivateCookieJar, Redirect), AppError> {
+    let updated_jar = session::end_all_sessions(&db_conn, jar, &session, &client).await?;
+    tracing::info!(user = session.user.name, "Logged out of all sessions");
+    Ok((updated_jar, Redirect::to("/htm/login")))
+}
+
+async fn authenticate(
+    state: &AppState,
+    db_conn: &PostgresPooledConnection,
+    login: &LoginForm,
+) -> Result<Option<User>, AppError> {
+    let password_config = &state.config.password_hash;
+
+    let Some(user) = db::users::get_user_by_name(db_conn, &login.username).await? else {
+        // spend the same time as a real verification so that unknown usernames can't be told apart
+        password::hash_password(password_config, &login.password)?;
+        return Ok(None);
+    };
+
+    match password::verify_password(password_config, &login.password, &user.password)? {
+        PasswordVerification::Valid => Ok(Some(user)),
+        PasswordVerification::ValidNeedsRehash => {
+            tracing::info!(user_id = user.id.to_string(), "Rehashing stored password");
+            let password_hash = password::hash_password(password_config, &login.password)?;
+            db::users::update_password(db_conn, &user.id, &password_hash).await?;
+            Ok(Some(user))
         }
-    } else {
-        let template = Htm;
-        render(template).map(|html| (jar, html.into_response()))
+        PasswordVerification::Invalid => Ok(None),
+    }
+}
+
+pub fn render_login(state: &AppState, csrf: &CsrfToken, error: Option<String>) -> RenderResult {
+    let template = Htm {
+        csrf_token: &csrf.0,
+        registration_open: state.config.registration.is_open(),
+        oidc_provider_name: state
+            .oidc
+            .as_ref()
+            .map(|_| state.config.oidc.provider_name.as_str()),
+        error,
+    };
+    render(template)
+}
+
+pub fn describe_wait(seconds: i32) -> String {
+    match seconds {
+        ..=1 => "a second".to_owned(),
+        2..60 => format!("{} seconds", seconds),
+        _ => format!("{} minutes", (seconds + 59) / 60),
     }
 }
@@ -2,8 +2,53 @@ use crate::error::AppError;
 use askama::Template;
 use axum::response::Html;
 
+pub mod access_tokens;
+pub mod admin;
+pub mod audit;
+pub mod email;
+pub mod filters;
 pub mod journal;
 pub mod login;
+pub mod oidc;
+pub mod passkeys;
+pub mod password_reset;
+pub mod register;
+pub mod search;
+pub mod sessions;
+pub mod settings;
+pub mod tags;
+pub mod totp;
+
+pub const PAGE_SIZE: i64 = 50;
+/// Page numbers past this are treated as this one, nobody pages that far and the offset of larger
+/// numbers would overflow
+const MAX_PAGE: i64 = 10_000;
+
+/// One page of a list, pages are numbered from 1
+pub struct Page<T> {
+    pub entries: Vec<T>,
+    pub number: i64,
+    pub has_next: bool,
+}
+
+impl<T> Page<T> {
+    /// Expects one entry more than a page holds, to tell whether there is a next page
+    pub fn new(mut entries: Vec<T>, number: i64) -> Self {
+        let has_next = entries.len() as i64 > PAGE_SIZE;
+        entries.truncate(PAGE_SIZE as usize);
+        Page {
+            entries,
+            number,
+            has_next,
+        }
+    }
+}
+
+/// Returns the requested page number, defaulting to the first, and the offset of its first entry
+pub fn page_offset(number: Option<i64>) -> (i64, i64) {
+    let number = number.unwrap_or(1).clamp(1, MAX_PAGE);
+    (number, (number - 1) * PAGE_SIZE)
+}
 
 pub type RenderResult = Result<Html<String>, AppError>;
 
@@ -11,8 +56,26 @@ pub fn render<T>(template: T) -> RenderResult
 where
     T: Template,
 {
-    template
-        .render()
-        .map(|content| Html(content))
-        .map_err(|error| AppError::from(error))
+    template.render().map(Html).map_err(AppError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_offset_defaults_to_the_first_page() {
+        assert_eq!(page_offset(None), (1, 0));
+        assert_eq!(page_offset(Some(0)), (1, 0));
+        assert_eq!(page_offset(Some(-5)), (1, 0));
+        assert_eq!(page_offset(Some(3)), (3, 2 * PAGE_SIZE));
+    }
+
+    #[test]
+    fn page_offset_clamps_huge_page_numbers() {
+        assert_eq!(
+            page_offset(Some(i64::MAX)),
+            (MAX_PAGE, (MAX_PAGE - 1) * PAGE_SIZE)
+        );
+    }
 }
@@ -0,0 +1,295 @@
+use crate::AppState;
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::users::User;
+use crate::db::{DatabaseConnection, PostgresPooledConnection};
+use crate::error::{self, AppError};
+use crate::extract::ClientInfo;
+use crate::htm::login::render_login;
+use crate::oidc::{OidcIdentity, OidcLoginState};
+use crate::password;
+use crate::session::{self, CurrentSession};
+use crate::token;
+use crate::verification;
+use axum::Extension;
+use axum::extract::{Query, State};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum_extra::extract::PrivateCookieJar;
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use chrono::{TimeDelta, Utc};
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+use time::Duration;
+use util::tracing::{self, instrument};
+use uuid::Uuid;
+
+const LOGIN_STATE_COOKIE: &str = "oidc_login";
+const LOGIN_STATE_PATH: &str = "/htm/login/oidc";
+const LOGIN_STATE_TTL_SECONDS: i64 = 600;
+/// How long ago the provider may have asked for the credentials when reauthenticating
+const REAUTHENTICATION_MAX_AGE_SECONDS: i64 = 300;
+/// Attempts at finding a free username before giving up
+const USERNAME_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// The name to show for the provider, if OpenID Connect login is enabled
+pub fn provider_name(state: &AppState) -> Option<&str> {
+    state
+        .oidc
+        .as_ref()
+        .map(|_| state.config.oidc.provider_name.as_str())
+}
+
+#[instrument(skip(state))]
+pub async fn get_oidc_login(
+    State(state): State<AppState>,
+    jar: PrivateCookieJar,
+) -> Result<(PrivateCookieJar, Response), AppError> {
+    let Some(provider) = &state.oidc else {
+        return Ok((jar, Redirect::to("/htm/login").into_response()));
+    };
+
+    let (url, login_state) = provider.authorization_request().await?;
+    Ok(redirect_to_provider(jar, &url, &login_state))
+}
+
+/// Sends the logged in user to the provider to confirm their identity, which stands in for the
+/// password they may not know on the settings page
+#[instrument(skip(state))]
+pub async fn post_oidc_reauthenticate(
+    State(state): State<AppState>,
+    jar: PrivateCookieJar,
+    session: CurrentSession,
+) -> Result<(PrivateCookieJar, Response), AppError> {
+    let Some(provider) = &state.oidc else {
+        return Ok((jar, Redirect::to("/htm/settings").into_response()));
+    };
+
+    let (url, login_state) = provider.reauthentication_request(&session.id).await?;
+    Ok(redirect_to_provider(jar, &url, &login_state))
+}
+
+fn redirect_to_provider(
+    jar: PrivateCookieJar,
+    url: &str,
+    login_state: &OidcLoginState,
+) -> (PrivateCookieJar, Response) {
+    let value = serde_json::to_string(login_state).expect("login state serializes");
+
+    // Lax, so the cookie is sent with the top-level redirect back from the provider
+    let cookie = Cookie::build((LOGIN_STATE_COOKIE, value))
+        .path(LOGIN_STATE_PATH)
+        .secure(true)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(Duration::seconds(LOGIN_STATE_TTL_SECONDS));
+
+    (jar.add(cookie), Redirect::to(url).into_response())
+}
+
+#[instrument(skip(state, csrf, client, params))]
+pub async fn get_oidc_callback(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    jar: PrivateCookieJar,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Query(params): Query<CallbackParams>,
+) -> Result<(PrivateCookieJar, Response), AppError> {
+    let Some(provider) = &state.oidc else {
+        return Ok((jar, Redirect::to("/htm/login").into_response()));
+    };
+
+    let login_state: Option<OidcLoginState> = jar
+        .get(LOGIN_STATE_COOKIE)
+        .and_then(|cookie| serde_json::from_str(cookie.value()).ok());
+    let jar = jar.remove(Cookie::build(LOGIN_STATE_COOKIE).path(LOGIN_STATE_PATH));
+
+    let fail = |jar, error: &str| {
+        render_login(&state, &csrf, Some(error.to_owned())).map(|html| (jar, html.into_response()))
+    };
+
+    if let Some(error) = params.error {
+        tracing::warn!(error, "The provider rejected the login");
+        return fail(jar, "Sign in was cancelled or denied");
+    }
+
+    let (Some(code), Some(login_state)) = (params.code, login_state) else {
+        return fail(jar, "The sign in request has expired, please try again");
+    };
+    let state_matches = params
+        .state
+        .is_some_and(|value| bool::from(value.as_bytes().ct_eq(login_state.state.as_bytes())));
+    if !state_matches {
+        tracing::warn!("OpenID Connect state mismatch");
+        return fail(jar, "The sign in request has expired, please try again");
+    }
+
+    let reauthenticated_session = login_state.reauthenticated_session;
+    let identity = match provider.exchange_code(code, login_state).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            tracing::warn!(error = e.to_string(), "OpenID Connect login failed");
+            return fail(jar, "Sign in failed, please try again");
+        }
+    };
+
+    if let Some(session_id) = reauthenticated_session {
+        reauthenticate(&db_conn, &session_id, &identity).await?;
+        return Ok((jar, Redirect::to("/htm/settings").into_response()));
+    }
+
+    let Some(user) = find_or_create_user(&state, &db_conn, &identity).await? else {
+        tracing::warn!(
+            subject = identity.subject,
+            "No user is linked to the identity and registration is not open"
+        );
+        return fail(jar, "Registration is closed");
+    };
+    if user.disabled {
+        tracing::warn!(user = user.name, "Login attempt of a disabled account");
+        return fail(jar, "This account is disabled");
+    }
+
+    if user.totp_enabled {
+        tracing::info!(
+            user = user.name,
+            "Identity verified, waiting for second factor"
+        );
+        let updated_jar =
+            session::start_pending_session(&db_conn, jar, &user, &client, false).await?;
+        return Ok((updated_jar, Redirect::to("/htm/login/totp").into_response()));
+    }
+
+    tracing::info!(user = user.name, "Logged in with OpenID Connect");
+    let updated_jar =
+        session::start_session(&db_conn, &state.config.session, jar, &user, &client, false).await?;
+    let landing = verification::landing_path(&state.config.email_verification, &user);
+    Ok((updated_jar, Redirect::to(landing).into_response()))
+}
+
+/// Marks the session as reauthenticated if the identity belongs to its user and the provider has
+/// just asked for their credentials. Otherwise the settings keep asking for the password.
+async fn reauthenticate(
+    db_conn: &PostgresPooledConnection,
+    session_id: &Uuid,
+    identity: &OidcIdentity,
+) -> Result<(), AppError> {
+    let fresh = identity.auth_time.is_some_and(|auth_time| {
+        Utc::now() - auth_time < TimeDelta::seconds(REAUTHENTICATION_MAX_AGE_SECONDS)
+    });
+    if !fresh {
+        tracing::warn!("The provider did not ask for the credentials again");
+        return Ok(());
+    }
+
+    let Some(user) =
+        db::identities::get_user_by_identity(db_conn, &identity.issuer, &identity.subject).await?
+    else {
+        tracing::warn!("Reauthenticated with an identity that is not linked to any user");
+        return Ok(());
+    };
+
+    if db::sessions::mark_reauthenticated(db_conn, &user.id, session_id).await? {
+        tracing::info!(user = user.name, "Reauthenticated with OpenID Connect");
+    } else {
+        tracing::warn!(
+            user = user.name,
+            "The session to reauthenticate is no longer active or belongs to someone else"
+        );
+    }
+    Ok(())
+}
+
+/// Returns the user linked to the identity. On its first login the identity is linked to an
+/// existing user with the same verified email if configured so, otherwise a new user is created
+/// just in time. That only happens while registration is open, `None` is returned otherwise.
+async fn find_or_create_user(
+    state: &AppState,
+    db_conn: &PostgresPooledConnection,
+    identity: &OidcIdentity,
+) -> Result<Option<User>, AppError> {
+    if let Some(user) =
+        db::identities::get_user_by_identity(db_conn, &identity.issuer, &identity.subject).await?
+    {
+        return Ok(Some(user));
+    }
+
+    // names can be chosen freely at the provider, only a verified address on both sides is proof
+    let existing = match &identity.verified_email {
+        Some(email) if state.config.oidc.link_existing_users => {
+            db::users::get_user_by_email(db_conn, email)
+                .await?
+                .filter(|user| user.email_verified)
+        }
+        _ => None,
+    };
+
+    let user = match existing {
+        Some(user) => {
+            tracing::info!(user = user.name, "Linking identity to existing user");
+            user
+        }
+        None if state.config.registration.allows_uninvited() => {
+            create_user(state, db_conn, identity).await?
+        }
+        None => return Ok(None),
+    };
+
+    db::identities::link_identity(db_conn, &identity.issuer, &identity.subject, &user.id).await?;
+    Ok(Some(user))
+}
+
+/// Users created through the provider get a random password nobody knows, they can only log in
+/// through the provider or a passkey
+async fn create_user(
+    state: &AppState,
+    db_conn: &PostgresPooledConnection,
+    identity: &OidcIdentity,
+) -> Result<User, AppError> {
+    let password_hash =
+        password::hash_password(&state.config.password_hash, &token::generate_token())?;
+    let base = base_username(identity);
+
+    for attempt in 0..USERNAME_ATTEMPTS {
+        let name = if attempt == 0 {
+            base.clone()
+        } else {
+            format!("{}-{}", base, rand::random_range(1000..10000))
+        };
+
+        if let Some(user) = db::users::create_user(&**db_conn, &name, None, &password_hash).await? {
+            tracing::info!(user = user.name, "Registered through OpenID Connect");
+            return Ok(user);
+        }
+    }
+
+    Err(error::oidc_error(format!(
+        "No free username found for {}",
+        base
+    )))
+}
+
+/// Derives a name from the `preferred_username` claim that passes the registration rules
+fn base_username(identity: &OidcIdentity) -> String {
+    let name: String = identity
+        .preferred_username
+        .as_deref()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        .take(54)
+        .collect();
+
+    if name.len() < 3 {
+        "user".to_owned()
+    } else {
+        name
+    }
+}
@@ -0,0 +1,303 @@
+use crate::AppState;
+use crate::audit::{self, AuditEvent};
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::DatabaseConnection;
+use crate::db::passkeys::StoredPasskey;
+use crate::error::{self, AppError};
+use crate::extract::{ClientInfo, ValidatedJson};
+use crate::htm::login::describe_wait;
+use crate::htm::{RenderResult, render};
+use crate::passkey;
+use crate::session::{self, CurrentSession, CurrentUser};
+use crate::throttle::LoginThrottle;
+use crate::verification;
+use askama::Template;
+use axum::extract::{FromRef, Path, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use axum_extra::extract::PrivateCookieJar;
+use axum_extra::extract::cookie::{Cookie, Key};
+use serde::{Deserialize, Serialize};
+use time::Duration;
+use util::tracing::{self, instrument};
+use uuid::Uuid;
+use validator::Validate;
+use webauthn_rs::prelude::{
+    CreationChallengeResponse, PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential,
+    RegisterPublicKeyCredential, RequestChallengeResponse,
+};
+
+const CHALLENGE_COOKIE: &str = "webauthn_challenge";
+const CHALLENGE_TTL_SECONDS: i32 = 300;
+
+#[derive(Deserialize, Validate)]
+pub struct RegistrationFinish {
+    #[validate(length(min = 1, max = 64, message = "Must be between 1 and 64 characters"))]
+    name: String,
+
+    credential: RegisterPublicKeyCredential,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct LoginStart {
+    /// Capped at the column width, like the password login
+    #[validate(length(min = 1, max = 255, message = "Must be between 1 and 255 characters"))]
+    username: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct LoginFinish {
+    credential: PublicKeyCredential,
+}
+
+#[derive(Serialize)]
+pub struct LoginFinished {
+    redirect: &'static str,
+}
+
+#[instrument(skip(csrf))]
+pub async fn get_passkeys(
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> RenderResult {
+    #[derive(Template)]
+    #[template(path = "passkeys.html")]
+    struct Htm {
+        csrf_token: String,
+        passkeys: Vec<StoredPasskey>,
+    }
+
+    let template = Htm {
+        csrf_token: csrf.0,
+        passkeys: db::passkeys::read_passkeys(&db_conn, &user.id).await?,
+    };
+    render(template)
+}
+
+#[instrument(skip(state))]
+pub async fn post_registration_start(
+    State(state): State<AppState>,
+    session: CurrentSession,
+    jar: PrivateCookieJar,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> Result<(PrivateCookieJar, Json<CreationChallengeResponse>), AppError> {
+    let user = &session.user;
+    let existing = db::passkeys::read_passkeys(&db_conn, &user.id)
+        .await?
+        .into_iter()
+        .map(|stored| stored.passkey.cred_id().clone())
+        .collect();
+
+    let (challenge, registration) = state.webauthn.start_passkey_registration(
+        user.id,
+        &user.name,
+        &user.name,
+        Some(existing),
+    )?;
+
+    let challenge_id = db::passkeys::create_challenge(
+        &db_conn,
+        &user.id,
+        Some(&session.id),
+        &registration,
+        CHALLENGE_TTL_SECONDS,
+    )
+    .await?;
+
+    Ok((add_challenge_cookie(jar, &challenge_id), Json(challenge)))
+}
+
+#[instrument(skip(state, client, registration))]
+pub async fn post_registration_finish(
+    State(state): State<AppState>,
+    session: CurrentSession,
+    jar: PrivateCookieJar,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    ValidatedJson(registration): ValidatedJson<RegistrationFinish>,
+) -> Result<(PrivateCookieJar, StatusCode), AppError> {
+    let challenge_id = challenge_id(&jar)?;
+    let (_, registration_state): (Uuid, PasskeyRegistration) =
+        db::passkeys::take_challenge(&db_conn, &challenge_id, Some(&session.id))
+            .await?
+            .ok_or_else(expired_error)?;
+
+    let passkey = state
+        .webauthn
+        .finish_passkey_registration(&registration.credential, &registration_state)?;
+
+    let user = &session.user;
+    if !db::passkeys::create_passkey(&db_conn, &user.id, &registration.name, &passkey).await? {
+        return Err(error::passkey_error(
+            "This passkey is already registered".to_owned(),
+        ));
+    }
+
+    audit::record(&db_conn, &client, AuditEvent::PasskeyAdded, &user.id).await?;
+    tracing::info!(user = user.name, "Registered a passkey");
+    Ok((remove_challenge_cookie(jar), StatusCode::CREATED))
+}
+
+#[instrument(skip(client))]
+pub async fn delete_passkey(
+    CurrentUser(user): CurrentUser,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Path(id): Path<Uuid>,
+) -> Result<(), AppError> {
+    db::passkeys::delete_passkey(&db_conn, &user.id, &id).await?;
+    audit::record(&db_conn, &client, AuditEvent::PasskeyDeleted, &user.id).await?;
+    tracing::info!(user = user.name, "Revoked a passkey");
+    Ok(())
+}
+
+#[instrument(skip(state, client, login))]
+pub async fn post_login_start(
+    State(state): State<AppState>,
+    jar: PrivateCookieJar,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    ValidatedJson(login): ValidatedJson<LoginStart>,
+) -> Result<(PrivateCookieJar, Json<RequestChallengeResponse>), AppError> {
+    let throttle = LoginThrottle::new(&state.config.login_throttle, &login.username, &client);
+    if let Some(seconds) = throttle.lockout_seconds(&db_conn).await? {
+        tracing::warn!(seconds, "Passkey login attempt while locked out");
+        return Err(error::passkey_error(format!(
+            "Too many failed login attempts. Try again in {}.",
+            describe_wait(seconds)
+        )));
+    }
+
+    let user = db::users::get_user_by_name(&db_conn, &login.username).await?;
+    let passkeys: Vec<_> = match &user {
+        Some(user) => db::passkeys::read_passkeys(&db_conn, &user.id)
+            .await?
+            .into_iter()
+            .map(|stored| stored.passkey)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    // Unknown usernames get the same answer as accounts without passkeys
+    let Some(user) = user.filter(|_| !passkeys.is_empty()) else {
+        let challenge = passkey::decoy_challenge(
+            &state.config.webauthn,
+            Key::from_ref(&state).signing(),
+            &login.username,
+        )?
+        .ok_or_else(|| error::passkey_error("Can not sign in with a passkey".to_owned()))?;
+        return Ok((add_challenge_cookie(jar, &Uuid::new_v4()), Json(challenge)));
+    };
+
+    let (challenge, authentication) = state.webauthn.start_passkey_authentication(&passkeys)?;
+
+    let challenge_id = db::passkeys::create_challenge(
+        &db_conn,
+        &user.id,
+        None,
+        &authentication,
+        CHALLENGE_TTL_SECONDS,
+    )
+    .await?;
+
+    Ok((add_challenge_cookie(jar, &challenge_id), Json(challenge)))
+}
+
+/// Passkeys require user verification, so they count as both factors and skip the TOTP step
+#[instrument(skip(state, client, login))]
+pub async fn post_login_finish(
+    State(state): State<AppState>,
+    jar: PrivateCookieJar,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    ValidatedJson(login): ValidatedJson<LoginFinish>,
+) -> Result<(PrivateCookieJar, Json<LoginFinished>), AppError> {
+    let challenge_id = challenge_id(&jar)?;
+    let (user_id, authentication): (Uuid, PasskeyAuthentication) =
+        db::passkeys::take_challenge(&db_conn, &challenge_id, None)
+            .await?
+            .ok_or_else(expired_error)?;
+
+    let user = db::users::get_user_by_id(&db_conn, &user_id)
+        .await?
+        .ok_or_else(|| error::passkey_error("Unknown account".to_owned()))?;
+
+    let throttle = LoginThrottle::new(&state.config.login_throttle, &user.name, &client);
+    if throttle.lockout_seconds(&db_conn).await?.is_some() {
+        audit::record_failed_login(&db_conn, &client, &user.name, "locked out").await?;
+        return Err(error::passkey_error(
+            "Too many failed login attempts".to_owned(),
+        ));
+    }
+
+    let result = match state
+        .webauthn
+        .finish_passkey_authentication(&login.credential, &authentication)
+    {
+        Ok(result) => result,
+        Err(e) => {
+            throttle.record_failure(&db_conn).await?;
+            audit::record_failed_login(&db_conn, &client, &user.name, "invalid passkey").await?;
+            return Err(e.into());
+        }
+    };
+    throttle.record_success(&db_conn).await?;
+
+    let mut stored = db::passkeys::read_passkeys(&db_conn, &user_id)
+        .await?
+        .into_iter()
+        .find(|stored| stored.passkey.cred_id() == result.cred_id())
+        .ok_or_else(|| error::passkey_error("Unknown passkey".to_owned()))?;
+    stored.passkey.update_credential(&result);
+    db::passkeys::update_passkey(&db_conn, &stored.id, &stored.passkey).await?;
+
+    if user.disabled {
+        audit::record_failed_login(&db_conn, &client, &user.name, "account disabled").await?;
+        return Err(error::passkey_error("This account is disabled".to_owned()));
+    }
+
+    tracing::info!(user = user.name, "Logged in with a passkey");
+    let updated_jar = session::start_session(
+        &db_conn,
+        &state.config.session,
+        remove_challenge_cookie(jar),
+        &user,
+        &client,
+        false,
+    )
+    .await?;
+
+    Ok((
+        updated_jar,
+        Json(LoginFinished {
+            redirect: verification::landing_path(&state.config.email_verification, &user),
+        }),
+    ))
+}
+
+fn add_challenge_cookie(jar: PrivateCookieJar, challenge_id: &Uuid) -> PrivateCookieJar {
+    let cookie = Cookie::build((CHALLENGE_COOKIE, challenge_id.hyphenated().to_string()))
+        .path("/htm/passkeys")
+        .secure(true)
+        .http_only(true)
+        .max_age(Duration::seconds(CHALLENGE_TTL_SECONDS.into()));
+
+    jar.add(cookie)
+}
+
+fn remove_challenge_cookie(jar: PrivateCookieJar) -> PrivateCookieJar {
+    jar.remove(Cookie::build(CHALLENGE_COOKIE).path("/htm/passkeys"))
+}
+
+fn challenge_id(jar: &PrivateCookieJar) -> Result<Uuid, AppError> {
+    jar.get(CHALLENGE_COOKIE)
+        .and_then(|cookie| Uuid::parse_str(cookie.value()).ok())
+        .ok_or_else(expired_error)
+}
+
+#[track_caller]
+fn expired_error() -> AppError {
+    error::passkey_error("The passkey request has expired, please try again".to_owned())
+}
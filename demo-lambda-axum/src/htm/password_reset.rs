@@ -0,0 +1,207 @@
+use crate::AppState;
+use crate::audit::{self, AuditEvent};
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::DatabaseConnection;
+use crate::db::users::User;
+use crate::error::AppError;
+use crate::extract::{ClientInfo, ValidatedForm};
+use crate::htm::{RenderResult, render};
+use crate::mail;
+use crate::password;
+use crate::token;
+use askama::Template;
+use axum::Extension;
+use axum::extract::{Query, State};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+use util::tracing::{self, instrument};
+use validator::Validate;
+
+/// Long enough to send the email in most cases. Sends that take longer are given up, because
+/// Lambda freezes the environment once the response is returned.
+const FORGOT_PASSWORD_RESPONSE_TIME: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Deserialize)]
+pub struct PasswordResetConfig {
+    ttl_minutes: i32,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ForgotForm {
+    #[validate(email(message = "Must be a valid email address"))]
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetParams {
+    #[serde(default)]
+    token: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ResetForm {
+    token: String,
+
+    #[validate(length(min = 8, max = 128, message = "Must be between 8 and 128 characters"))]
+    password: String,
+
+    #[validate(must_match(other = "password", message = "Passwords do not match"))]
+    password_confirmation: String,
+}
+
+#[derive(Template)]
+#[template(path = "password_forgot.html")]
+struct ForgotHtm<'a> {
+    csrf_token: &'a str,
+    sent: bool,
+}
+
+#[derive(Template)]
+#[template(path = "password_reset.html")]
+struct ResetHtm<'a> {
+    csrf_token: &'a str,
+    token: &'a str,
+    valid: bool,
+    done: bool,
+}
+
+#[derive(Template)]
+#[template(path = "email/password_reset.txt")]
+struct ResetEmail<'a> {
+    name: &'a str,
+    link: &'a str,
+    ttl_minutes: i32,
+}
+
+#[instrument(skip(csrf))]
+pub async fn get_forgot_password(Extension(csrf): Extension<CsrfToken>) -> RenderResult {
+    let template = ForgotHtm {
+        csrf_token: &csrf.0,
+        sent: false,
+    };
+    render(template)
+}
+
+/// Always reports the email as sent, so that registered addresses can't be told apart. For the
+/// same reason every answer is held back to the same time, and the email is sent within it.
+#[instrument(skip(state, csrf, form))]
+pub async fn post_forgot_password(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    ValidatedForm(form): ValidatedForm<ForgotForm>,
+) -> RenderResult {
+    let respond_at = Instant::now() + FORGOT_PASSWORD_RESPONSE_TIME;
+
+    let reset = match db::users::get_user_by_email(&db_conn, &form.email).await? {
+        Some(user) => {
+            let reset_token = token::generate_token();
+            let ttl_minutes = state.config.password_reset.ttl_minutes;
+            db::password_resets::create_reset_token(
+                &db_conn,
+                &user.id,
+                &token::hash_token(&reset_token),
+                ttl_minutes,
+            )
+            .await?;
+            Some((user, reset_token))
+        }
+        None => None,
+    };
+
+    // The pool may only have one connection, which shouldn't wait out the response time
+    drop(db_conn);
+
+    if let Some((user, reset_token)) = reset {
+        match time::timeout_at(respond_at, send_reset_email(&state, &user, &reset_token)).await {
+            Ok(Ok(())) => tracing::info!(user = user.name, "Sent password reset email"),
+            Ok(Err(e)) => {
+                tracing::error!(error = e.to_string(), "Failed to send password reset email")
+            }
+            Err(_) => tracing::error!("Timed out sending password reset email"),
+        }
+    }
+
+    time::sleep_until(respond_at).await;
+
+    let template = ForgotHtm {
+        csrf_token: &csrf.0,
+        sent: true,
+    };
+    render(template)
+}
+
+#[instrument(skip(csrf, params))]
+pub async fn get_reset_password(
+    Extension(csrf): Extension<CsrfToken>,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Query(params): Query<ResetParams>,
+) -> RenderResult {
+    let valid =
+        db::password_resets::is_valid_reset_token(&db_conn, &token::hash_token(&params.token))
+            .await?;
+
+    let template = ResetHtm {
+        csrf_token: &csrf.0,
+        token: &params.token,
+        valid,
+        done: false,
+    };
+    render(template)
+}
+
+/// Sets the new password and ends every session, in case the old password was compromised
+#[instrument(skip(state, csrf, client, form))]
+pub async fn post_reset_password(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    ValidatedForm(form): ValidatedForm<ResetForm>,
+) -> RenderResult {
+    let user_id =
+        db::password_resets::take_reset_token(&db_conn, &token::hash_token(&form.token)).await?;
+
+    if let Some(user_id) = user_id {
+        let password_hash = password::hash_password(&state.config.password_hash, &form.password)?;
+        db::users::update_password(&db_conn, &user_id, &password_hash).await?;
+        db::sessions::revoke_user_sessions(&db_conn, &user_id).await?;
+        audit::record(&db_conn, &client, AuditEvent::PasswordReset, &user_id).await?;
+        tracing::info!(user_id = user_id.to_string(), "Reset password");
+    }
+
+    let template = ResetHtm {
+        csrf_token: &csrf.0,
+        token: "",
+        valid: user_id.is_some(),
+        done: user_id.is_some(),
+    };
+    render(template)
+}
+
+async fn send_reset_email(
+    state: &AppState,
+    user: &User,
+    reset_token: &str,
+) -> Result<(), AppError> {
+    let Some(email) = &user.email else {
+        return Ok(());
+    };
+
+    let mail_config = &state.config.mail;
+    let link = format!(
+        "{}/htm/password/reset?token={}",
+        mail_config.base_url, reset_token
+    );
+    let body = ResetEmail {
+        name: &user.name,
+        link: &link,
+        ttl_minutes: state.config.password_reset.ttl_minutes,
+    }
+    .render()?;
+
+    let message = mail::message(mail_config, email, "Reset your Journal password", body)?;
+    state.mailer.send(message).await
+}
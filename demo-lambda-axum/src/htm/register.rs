@@ -0,0 +1,179 @@
+use crate::AppState;
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::DatabaseConnection;
+use crate::error::AppError;
+use crate::extract::{ClientInfo, ValidatedForm};
+use crate::htm::email::send_verification_email;
+use crate::htm::{RenderResult, render};
+use crate::password;
+use crate::serde_decorators::empty_string_as_none;
+use crate::session;
+use crate::token;
+use crate::verification;
+use askama::Template;
+use axum::Extension;
+use axum::extract::State;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum_extra::extract::PrivateCookieJar;
+use serde::Deserialize;
+use util::tracing::{self, instrument};
+use validator::{Validate, ValidationError};
+
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationMode {
+    Open,
+    InviteOnly,
+    Closed,
+}
+
+/// In `invite_only` mode every registration uses up one of the codes administrators create on the
+/// admin page
+#[derive(Clone, Deserialize)]
+pub struct RegistrationConfig {
+    mode: RegistrationMode,
+}
+
+impl RegistrationConfig {
+    pub fn is_open(&self) -> bool {
+        self.mode != RegistrationMode::Closed
+    }
+
+    /// Whether accounts may be created without going through the registration form, like the
+    /// ones created on the first OpenID Connect login, which have no way to present an invite code
+    pub fn allows_uninvited(&self) -> bool {
+        self.mode == RegistrationMode::Open
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct RegisterForm {
+    #[validate(
+        length(min = 3, max = 64, message = "Must be between 3 and 64 characters"),
+        custom(function = "validate_username")
+    )]
+    username: String,
+
+    /// Only needed to reset a forgotten password
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    #[validate(
+        email(message = "Must be a valid email address"),
+        length(max = 255, message = "Must be at most 255 characters")
+    )]
+    email: Option<String>,
+
+    #[validate(length(min = 8, max = 128, message = "Must be between 8 and 128 characters"))]
+    password: String,
+
+    #[validate(must_match(other = "password", message = "Passwords do not match"))]
+    password_confirmation: String,
+
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    invite_code: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "register.html")]
+struct Htm<'a> {
+    csrf_token: &'a str,
+    mode: RegistrationMode,
+    error: Option<&'a str>,
+}
+
+#[instrument(skip(state, csrf))]
+pub async fn get_register(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> RenderResult {
+    let template = Htm {
+        csrf_token: &csrf.0,
+        mode: state.config.registration.mode,
+        error: None,
+    };
+    render(template)
+}
+
+#[instrument(skip(state, csrf, client, registration))]
+pub async fn post_register(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    jar: PrivateCookieJar,
+    client: ClientInfo,
+    DatabaseConnection(mut db_conn): DatabaseConnection,
+    ValidatedForm(registration): ValidatedForm<RegisterForm>,
+) -> Result<(PrivateCookieJar, Response), AppError> {
+    let config = &state.config.registration;
+
+    // the code is only used up if the user is created, a rollback puts it back
+    let transaction = db_conn.transaction().await?;
+
+    // only hashes are stored, so looking a code up doesn't leak it through timing
+    let rejection = match (config.mode, &registration.invite_code) {
+        (RegistrationMode::Open, _) => None,
+        (RegistrationMode::InviteOnly, Some(code)) => {
+            let code_hash = token::hash_token(code);
+            if db::invite_codes::take_invite_code(&transaction, &code_hash).await? {
+                None
+            } else {
+                Some("Invalid invite code")
+            }
+        }
+        (RegistrationMode::InviteOnly, None) => Some("Invalid invite code"),
+        (RegistrationMode::Closed, _) => Some("Registration is closed"),
+    };
+
+    if let Some(error) = rejection {
+        let template = Htm {
+            csrf_token: &csrf.0,
+            mode: config.mode,
+            error: Some(error),
+        };
+        return render(template).map(|html| (jar, html.into_response()));
+    }
+
+    let password_hash =
+        password::hash_password(&state.config.password_hash, &registration.password)?;
+
+    let Some(user) = db::users::create_user(
+        &transaction,
+        &registration.username,
+        registration.email.as_deref(),
+        &password_hash,
+    )
+    .await?
+    else {
+        let template = Htm {
+            csrf_token: &csrf.0,
+            mode: config.mode,
+            error: Some("That username or email is already taken"),
+        };
+        return render(template).map(|html| (jar, html.into_response()));
+    };
+    transaction.commit().await?;
+
+    tracing::info!(user = user.name, "Registered");
+    if let Some(email) = &user.email {
+        // the link can be sent again from the email page
+        if let Err(e) = send_verification_email(&state, &user.id, &user.name, email).await {
+            tracing::error!(error = e.to_string(), "Failed to send verification email");
+        }
+    }
+
+    let updated_jar =
+        session::start_session(&db_conn, &state.config.session, jar, &user, &client, false).await?;
+    let landing = verification::landing_path(&state.config.email_verification, &user);
+    Ok((updated_jar, Redirect::to(landing).into_response()))
+}
+
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    if username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        Ok(())
+    } else {
+        Err(ValidationError::new("username")
+            .with_message("Only letters, digits, '_', '-' and '.' are allowed".into()))
+    }
+}
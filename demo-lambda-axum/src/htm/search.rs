@@ -0,0 +1,80 @@
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::DatabaseConnection;
+use crate::db::entries::{SearchFilter, SearchResult};
+use crate::htm::{PAGE_SIZE, Page, RenderResult, page_offset, render};
+use crate::serde_decorators::empty_string_as_none;
+use crate::session::CurrentUser;
+use askama::Template;
+use axum::Extension;
+use axum::extract::Query;
+use chrono::NaiveDate;
+use serde::Deserialize;
+use util::tracing::{self, instrument};
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    q: Option<String>,
+
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    from: Option<NaiveDate>,
+
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    to: Option<NaiveDate>,
+
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    page: Option<i64>,
+}
+
+/// Full-text search over the entries of the current user, the most relevant first
+#[instrument(skip(csrf))]
+pub async fn get_search(
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Query(params): Query<SearchParams>,
+) -> RenderResult {
+    #[derive(Template)]
+    #[template(path = "search.html")]
+    struct Htm<'a> {
+        csrf_token: &'a str,
+        filter: SearchFilter,
+        /// The search as a query string, for the page links
+        filter_query: String,
+        /// Nothing was searched for yet
+        page: Option<Page<SearchResult>>,
+    }
+
+    let filter = SearchFilter {
+        query: params.q.unwrap_or_default(),
+        from: params.from,
+        to: params.to,
+    };
+
+    let page = if filter.query.trim().is_empty() {
+        None
+    } else {
+        let (number, offset) = page_offset(params.page);
+        let results =
+            db::entries::search_entries(&db_conn, &user.id, &filter, PAGE_SIZE + 1, offset).await?;
+        Some(Page::new(results, number))
+    };
+
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    query.append_pair("q", &filter.query);
+    if let Some(from) = &filter.from {
+        query.append_pair("from", &from.to_string());
+    }
+    if let Some(to) = &filter.to {
+        query.append_pair("to", &to.to_string());
+    }
+
+    let template = Htm {
+        csrf_token: &csrf.0,
+        filter_query: query.finish(),
+        filter,
+        page,
+    };
+    render(template)
+}
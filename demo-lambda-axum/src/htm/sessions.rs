@@ -0,0 +1,114 @@
+use crate::audit::{self, AuditEvent};
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::DatabaseConnection;
+use crate::db::sessions::SessionSummary;
+use crate::error::AppError;
+use crate::extract::ClientInfo;
+use crate::htm::{RenderResult, render};
+use crate::session::{self, CurrentSession};
+use askama::Template;
+use axum::Extension;
+use axum::extract::Path;
+use axum::response::{IntoResponse, Response};
+use axum_extra::extract::PrivateCookieJar;
+use util::tracing::{self, instrument};
+use uuid::Uuid;
+
+/// Browsers and platforms recognized in user agents, the first match wins. Edge and Opera
+/// also claim to be Chrome, and Chrome also claims to be Safari.
+const BROWSERS: [(&str, &str); 6] = [
+    ("Edg/", "Edge"),
+    ("OPR/", "Opera"),
+    ("Firefox/", "Firefox"),
+    ("Chrome/", "Chrome"),
+    ("Safari/", "Safari"),
+    ("curl/", "curl"),
+];
+const PLATFORMS: [(&str, &str); 6] = [
+    ("Android", "Android"),
+    ("iPhone", "iPhone"),
+    ("iPad", "iPad"),
+    ("Windows", "Windows"),
+    ("Mac OS X", "macOS"),
+    ("Linux", "Linux"),
+];
+
+#[derive(Template)]
+#[template(path = "sessions.html")]
+struct Htm<'a> {
+    csrf_token: &'a str,
+    current_session_id: Uuid,
+    sessions: Vec<SessionRow>,
+}
+
+struct SessionRow {
+    session: SessionSummary,
+    device: String,
+}
+
+#[instrument(skip(csrf))]
+pub async fn get_sessions(
+    Extension(csrf): Extension<CsrfToken>,
+    session: CurrentSession,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> RenderResult {
+    let sessions = db::sessions::read_active_sessions(&db_conn, &session.user.id).await?;
+
+    let template = Htm {
+        csrf_token: &csrf.0,
+        current_session_id: session.id,
+        sessions: sessions
+            .into_iter()
+            .map(|session| SessionRow {
+                device: describe_device(session.user_agent.as_deref().unwrap_or_default()),
+                session,
+            })
+            .collect(),
+    };
+    render(template)
+}
+
+/// Revoking the current session logs out, like the logout button
+#[instrument(skip(client))]
+pub async fn delete_session(
+    jar: PrivateCookieJar,
+    session: CurrentSession,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Path(id): Path<Uuid>,
+) -> Result<(PrivateCookieJar, Response), AppError> {
+    if id == session.id {
+        let updated_jar = session::end_session(&db_conn, jar, &session, &client).await?;
+        tracing::info!(user = session.user.name, "Logged out");
+        return Ok((updated_jar, [("HX-Redirect", "/htm/login")].into_response()));
+    }
+
+    if db::sessions::revoke_user_session(&db_conn, &session.user.id, &id).await? {
+        audit::record(
+            &db_conn,
+            &client,
+            AuditEvent::SessionRevoked,
+            &session.user.id,
+        )
+        .await?;
+        tracing::info!(user = session.user.name, "Revoked a session");
+    }
+    Ok((jar, ().into_response()))
+}
+
+/// A short description like "Firefox on Linux"
+fn describe_device(user_agent: &str) -> String {
+    let find = |names: &[(&str, &'static str)]| {
+        names
+            .iter()
+            .find(|(marker, _)| user_agent.contains(marker))
+            .map(|(_, name)| *name)
+    };
+
+    match (find(&BROWSERS), find(&PLATFORMS)) {
+        (Some(browser), Some(platform)) => format!("{} on {}", browser, platform),
+        (Some(name), None) | (None, Some(name)) => name.to_owned(),
+        (None, None) => "Unknown device".to_owned(),
+    }
+}
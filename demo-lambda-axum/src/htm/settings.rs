@@ -0,0 +1,324 @@
+use crate::AppState;
+use crate::audit::{self, AuditEvent};
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::access_tokens::AccessToken;
+use crate::db::audit_events::AuditEntry;
+use crate::db::entries::Entry;
+use crate::db::sessions::SessionSummary;
+use crate::db::users::{Role, User};
+use crate::db::{DatabaseConnection, PostgresPooledConnection};
+use crate::error::AppError;
+use crate::extract::{ClientInfo, FieldErrors, ValidatedForm};
+use crate::htm::oidc;
+use crate::htm::register::validate_username;
+use crate::htm::{RenderResult, render};
+use crate::password::{self, PasswordVerification};
+use crate::session::{self, CurrentSession, CurrentUser};
+use askama::Template;
+use axum::Extension;
+use axum::extract::State;
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum_extra::extract::PrivateCookieJar;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use util::tracing::{self, instrument};
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Clone, Deserialize)]
+pub struct AccountDeletionConfig {
+    /// Days between requesting the deletion and the purge task removing the account
+    grace_days: i32,
+}
+
+/// How long confirming the identity at the OpenID Connect provider stands in for the password
+const REAUTHENTICATION_MINUTES: i32 = 5;
+
+#[derive(Deserialize, Validate)]
+pub struct PasswordForm {
+    /// Not needed after reauthenticating with the OpenID Connect provider
+    #[serde(default)]
+    current_password: String,
+
+    #[validate(length(min = 8, max = 128, message = "Must be between 8 and 128 characters"))]
+    new_password: String,
+
+    #[validate(must_match(other = "new_password", message = "Passwords do not match"))]
+    new_password_confirmation: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct UsernameForm {
+    #[validate(
+        length(min = 3, max = 64, message = "Must be between 3 and 64 characters"),
+        custom(function = "validate_username")
+    )]
+    username: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct DeleteAccountForm {
+    /// Not needed after reauthenticating with the OpenID Connect provider
+    #[serde(default)]
+    password: String,
+}
+
+#[derive(Template)]
+#[template(path = "settings.html")]
+struct Htm<'a> {
+    csrf_token: &'a str,
+    username: &'a str,
+    delete_after: Option<NaiveDateTime>,
+    grace_days: i32,
+    message: Option<&'a str>,
+    password_errors: FieldErrors,
+    username_errors: FieldErrors,
+    error: Option<&'a str>,
+    oidc_provider_name: Option<&'a str>,
+    /// The password fields are left out while this is set
+    reauthenticated: bool,
+}
+
+impl<'a> Htm<'a> {
+    fn new(state: &'a AppState, csrf: &'a CsrfToken, user: &'a User) -> Self {
+        Htm {
+            csrf_token: &csrf.0,
+            username: &user.name,
+            delete_after: user.delete_after,
+            grace_days: state.config.account_deletion.grace_days,
+            message: None,
+            password_errors: FieldErrors::default(),
+            username_errors: FieldErrors::default(),
+            error: None,
+            oidc_provider_name: oidc::provider_name(state),
+            reauthenticated: false,
+        }
+    }
+}
+
+/// Everything stored about a user, as downloaded from the settings page
+#[derive(Serialize)]
+struct Export<'a> {
+    exported_at: DateTime<Utc>,
+    profile: Profile<'a>,
+    entries: Vec<Entry>,
+    sessions: Vec<SessionSummary>,
+    access_tokens: Vec<AccessToken>,
+    activity: Vec<AuditEntry>,
+}
+
+#[derive(Serialize)]
+struct Profile<'a> {
+    id: Uuid,
+    name: &'a str,
+    email: Option<&'a str>,
+    email_verified: bool,
+    totp_enabled: bool,
+    role: &'static str,
+}
+
+#[instrument(skip(state, csrf))]
+pub async fn get_settings(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    session: CurrentSession,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> RenderResult {
+    render(Htm {
+        reauthenticated: is_reauthenticated(&db_conn, &session).await?,
+        ..Htm::new(&state, &csrf, &session.user)
+    })
+}
+
+/// Keeps the current session and ends all others, in case the old password was compromised
+#[instrument(skip(state, csrf, client, form))]
+pub async fn post_password(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    session: CurrentSession,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    form: Result<ValidatedForm<PasswordForm>, AppError>,
+) -> RenderResult {
+    let user = &session.user;
+    let reauthenticated = is_reauthenticated(&db_conn, &session).await?;
+    let form = match ValidatedForm::or_field_errors(form)? {
+        Ok(form) => form,
+        Err(password_errors) => {
+            return render(Htm {
+                password_errors,
+                reauthenticated,
+                ..Htm::new(&state, &csrf, user)
+            });
+        }
+    };
+
+    let config = &state.config.password_hash;
+    if !reauthenticated
+        && let PasswordVerification::Invalid =
+            password::verify_password(config, &form.current_password, &user.password)?
+    {
+        return render(Htm {
+            password_errors: FieldErrors::default().with("current_password", "Invalid password"),
+            ..Htm::new(&state, &csrf, user)
+        });
+    }
+
+    let password_hash = password::hash_password(config, &form.new_password)?;
+    db::users::update_password(&db_conn, &user.id, &password_hash).await?;
+    db::sessions::revoke_other_sessions(&db_conn, &user.id, &session.id).await?;
+    audit::record(&db_conn, &client, AuditEvent::PasswordChanged, &user.id).await?;
+    tracing::info!(user = user.name, "Changed password");
+
+    render(Htm {
+        message: Some("Your password was changed and your other sessions were logged out"),
+        ..Htm::new(&state, &csrf, user)
+    })
+}
+
+#[instrument(skip(state, csrf, client, form))]
+pub async fn post_username(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    form: Result<ValidatedForm<UsernameForm>, AppError>,
+) -> RenderResult {
+    let form = match ValidatedForm::or_field_errors(form)? {
+        Ok(form) => form,
+        Err(username_errors) => {
+            return render(Htm {
+                username_errors,
+                ..Htm::new(&state, &csrf, &user)
+            });
+        }
+    };
+
+    if form.username == user.name {
+        return render(Htm::new(&state, &csrf, &user));
+    }
+
+    if !db::users::update_name(&db_conn, &user.id, &form.username).await? {
+        return render(Htm {
+            username_errors: FieldErrors::default().with("username", "That username is taken"),
+            ..Htm::new(&state, &csrf, &user)
+        });
+    }
+
+    audit::record(&db_conn, &client, AuditEvent::UsernameChanged, &user.id).await?;
+    tracing::info!(
+        user = user.name,
+        new_name = form.username,
+        "Changed username"
+    );
+
+    render(Htm {
+        username: &form.username,
+        message: Some("Your username was changed"),
+        ..Htm::new(&state, &csrf, &user)
+    })
+}
+
+#[instrument(skip(client))]
+pub async fn get_export(
+    CurrentUser(user): CurrentUser,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> Result<Response, AppError> {
+    let export = Export {
+        exported_at: Utc::now(),
+        profile: Profile {
+            id: user.id,
+            name: &user.name,
+            email: user.email.as_deref(),
+            email_verified: user.email_verified,
+            totp_enabled: user.totp_enabled,
+            role: Role::into(user.role),
+        },
+        entries: db::entries::read_all_entries(&db_conn, &user.id).await?,
+        sessions: db::sessions::read_user_sessions(&db_conn, &user.id).await?,
+        access_tokens: db::access_tokens::read_access_tokens(&db_conn, &user.id).await?,
+        activity: db::audit_events::read_user_audit_events(&db_conn, &user.id, i64::MAX, 0).await?,
+    };
+    let body = serde_json::to_string_pretty(&export).expect("export serializes");
+
+    audit::record(&db_conn, &client, AuditEvent::DataExported, &user.id).await?;
+    tracing::info!(user = user.name, "Exported account data");
+
+    let disposition = format!(
+        "attachment; filename=\"journal-export-{}.json\"",
+        export.exported_at.format("%Y-%m-%d")
+    );
+    Ok((
+        [
+            (CONTENT_TYPE, "application/json".to_owned()),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Schedules the deletion and logs out everywhere. Logging in again during the grace period
+/// allows cancelling it.
+#[instrument(skip(state, csrf, client, form))]
+pub async fn post_delete_account(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    jar: PrivateCookieJar,
+    session: CurrentSession,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    ValidatedForm(form): ValidatedForm<DeleteAccountForm>,
+) -> Result<(PrivateCookieJar, Response), AppError> {
+    let user = &session.user;
+    if !is_reauthenticated(&db_conn, &session).await?
+        && let PasswordVerification::Invalid =
+            password::verify_password(&state.config.password_hash, &form.password, &user.password)?
+    {
+        let html = render(Htm {
+            error: Some("Invalid password"),
+            ..Htm::new(&state, &csrf, user)
+        })?;
+        return Ok((jar, html.into_response()));
+    }
+
+    let delete_after =
+        db::users::schedule_deletion(&db_conn, &user.id, state.config.account_deletion.grace_days)
+            .await?;
+    // tokens can't be revived by logging in, so they go right away
+    db::access_tokens::delete_user_access_tokens(&db_conn, &user.id).await?;
+    audit::record(&db_conn, &client, AuditEvent::DeletionScheduled, &user.id).await?;
+    tracing::info!(
+        user = user.name,
+        delete_after = delete_after.to_string(),
+        "Scheduled account deletion"
+    );
+
+    let updated_jar = session::end_all_sessions(&db_conn, jar, &session, &client).await?;
+    Ok((updated_jar, Redirect::to("/htm/login").into_response()))
+}
+
+#[instrument(skip(client))]
+pub async fn post_cancel_deletion(
+    CurrentUser(user): CurrentUser,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> Result<Redirect, AppError> {
+    db::users::cancel_deletion(&db_conn, &user.id).await?;
+    audit::record(&db_conn, &client, AuditEvent::DeletionCancelled, &user.id).await?;
+    tracing::info!(user = user.name, "Cancelled account deletion");
+    Ok(Redirect::to("/htm/settings"))
+}
+
+/// Whether the user of the session recently confirmed their identity with the OpenID Connect
+/// provider, which stands in for the password
+pub async fn is_reauthenticated(
+    db_conn: &PostgresPooledConnection,
+    session: &CurrentSession,
+) -> Result<bool, AppError> {
+    db::sessions::is_reauthenticated(db_conn, &session.id, REAUTHENTICATION_MINUTES).await
+}
@@ -0,0 +1,37 @@
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::DatabaseConnection;
+use crate::db::entries::Entry;
+use crate::htm::{RenderResult, filters, render};
+use crate::session::CurrentUser;
+use askama::Template;
+use axum::Extension;
+use axum::extract::Path;
+use util::tracing::{self, instrument};
+
+/// The entries with the tag, across all dates
+#[instrument(skip(csrf))]
+pub async fn get_tag(
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    Path(tag): Path<String>,
+) -> RenderResult {
+    #[derive(Template)]
+    #[template(path = "tag.html")]
+    struct Htm<'a> {
+        csrf_token: &'a str,
+        tag: String,
+        entries: Vec<Entry>,
+    }
+
+    let tag = tag.trim_start_matches('#').to_lowercase();
+    let entries = db::entries::read_tagged_entries(&db_conn, &user.id, &tag).await?;
+
+    let template = Htm {
+        csrf_token: &csrf.0,
+        tag,
+        entries,
+    };
+    render(template)
+}
@@ -0,0 +1,277 @@
+use crate::AppState;
+use crate::audit::{self, AuditEvent};
+use crate::csrf::CsrfToken;
+use crate::db;
+use crate::db::users::User;
+use crate::db::{DatabaseConnection, PostgresPooledConnection};
+use crate::error::AppError;
+use crate::extract::{ClientInfo, ValidatedForm};
+use crate::htm::{RenderResult, render};
+use crate::session::{self, CurrentUser, PendingSession};
+use crate::throttle::LoginThrottle;
+use crate::token;
+use crate::totp;
+use crate::verification;
+use askama::Template;
+use axum::Extension;
+use axum::extract::State;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum_extra::extract::PrivateCookieJar;
+use serde::Deserialize;
+use util::tracing::{self, instrument};
+use validator::Validate;
+
+#[derive(Deserialize, Validate)]
+pub struct CodeForm {
+    /// Either a current TOTP code or an unused recovery code
+    #[validate(length(min = 1, max = 32, message = "Must be between 1 and 32 characters"))]
+    code: String,
+}
+
+#[derive(Template)]
+#[template(path = "login_totp.html")]
+struct LoginTotpHtm<'a> {
+    csrf_token: &'a str,
+    error: Option<String>,
+}
+
+#[instrument(skip(csrf))]
+pub async fn get_login_totp(
+    Extension(csrf): Extension<CsrfToken>,
+    pending: Option<Extension<PendingSession>>,
+) -> Result<Response, AppError> {
+    if pending.is_none() {
+        return Ok(Redirect::to("/htm/index").into_response());
+    }
+
+    let template = LoginTotpHtm {
+        csrf_token: &csrf.0,
+        error: None,
+    };
+    render(template).map(IntoResponse::into_response)
+}
+
+#[instrument(skip(state, csrf, client, form))]
+pub async fn post_login_totp(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    jar: PrivateCookieJar,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    pending: Option<Extension<PendingSession>>,
+    ValidatedForm(form): ValidatedForm<CodeForm>,
+) -> Result<(PrivateCookieJar, Response), AppError> {
+    let Some(Extension(pending)) = pending else {
+        return Ok((jar, Redirect::to("/htm/index").into_response()));
+    };
+
+    let user = &pending.session.user;
+    let throttle = LoginThrottle::new(&state.config.login_throttle, &user.name, &client);
+
+    let error = if throttle.lockout_seconds(&db_conn).await?.is_some() {
+        tracing::warn!("Second factor attempt while locked out");
+        "Too many failed attempts. Log in again later."
+    } else if verify_second_factor(&state, &db_conn, user, &form.code).await? {
+        throttle.record_success(&db_conn).await?;
+
+        tracing::info!(user = user.name, "Completed second factor");
+        let updated_jar = session::complete_pending_session(
+            &db_conn,
+            &state.config.session,
+            jar,
+            &pending,
+            &client,
+        )
+        .await?;
+        let landing = verification::landing_path(&state.config.email_verification, user);
+        return Ok((updated_jar, Redirect::to(landing).into_response()));
+    } else {
+        throttle.record_failure(&db_conn).await?;
+        audit::record_failed_login(&db_conn, &client, &user.name, "invalid second factor").await?;
+        "Invalid code"
+    };
+
+    let template = LoginTotpHtm {
+        csrf_token: &csrf.0,
+        error: Some(error.to_owned()),
+    };
+    render(template).map(|html| (jar, html.into_response()))
+}
+
+#[derive(Template)]
+#[template(path = "totp.html")]
+struct TotpHtm<'a> {
+    csrf_token: &'a str,
+    enabled: bool,
+    /// A secret was set up and waits to be confirmed
+    pending: bool,
+    otpauth_url: String,
+    qr_code_svg: String,
+    error: Option<&'a str>,
+}
+
+#[instrument(skip(state, csrf))]
+pub async fn get_totp(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> RenderResult {
+    render_totp(&state, &csrf, &db_conn, &user, None).await
+}
+
+#[instrument(skip(state, csrf, client, form))]
+pub async fn post_totp_enable(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    ValidatedForm(form): ValidatedForm<CodeForm>,
+) -> RenderResult {
+    #[derive(Template)]
+    #[template(path = "totp_recovery_codes.html")]
+    struct Htm<'a> {
+        csrf_token: &'a str,
+        recovery_codes: Vec<String>,
+    }
+    if user.totp_enabled {
+        return render_totp(&state, &csrf, &db_conn, &user, None).await;
+    }
+
+    let Some(encrypted_secret) = db::totp::get_secret(&db_conn, &user.id).await? else {
+        return render_totp(&state, &csrf, &db_conn, &user, None).await;
+    };
+
+    let secret = state.totp_cipher.decrypt(&user.id, &encrypted_secret)?;
+    let totp = totp::totp(&state.config.totp, secret, &user.name)?;
+    let accepted = match totp::check_code(&totp, &form.code)? {
+        Some(step) => db::totp::accept_step(&db_conn, &user.id, step).await?,
+        None => false,
+    };
+    if !accepted {
+        // keep the pending secret, the user has likely already scanned it
+        let template = TotpHtm {
+            csrf_token: &csrf.0,
+            enabled: false,
+            pending: true,
+            otpauth_url: totp.get_url(),
+            qr_code_svg: totp::qr_code_svg(&totp)?,
+            error: Some("Invalid code, please try again"),
+        };
+        return render(template);
+    }
+
+    let recovery_codes = totp::generate_recovery_codes();
+    let recovery_code_hashes: Vec<Vec<u8>> = recovery_codes
+        .iter()
+        .map(|code| token::hash_token(&totp::normalize_recovery_code(code)))
+        .collect();
+    db::totp::enable(&db_conn, &user.id, &recovery_code_hashes).await?;
+    audit::record(&db_conn, &client, AuditEvent::TotpEnabled, &user.id).await?;
+    tracing::info!(user = user.name, "Enabled two-factor authentication");
+
+    let template = Htm {
+        csrf_token: &csrf.0,
+        recovery_codes,
+    };
+    render(template)
+}
+
+#[instrument(skip(state, csrf, client, form))]
+pub async fn post_totp_disable(
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+    CurrentUser(user): CurrentUser,
+    client: ClientInfo,
+    DatabaseConnection(db_conn): DatabaseConnection,
+    ValidatedForm(form): ValidatedForm<CodeForm>,
+) -> Result<Response, AppError> {
+    if user.totp_enabled && !verify_second_factor(&state, &db_conn, &user, &form.code).await? {
+        return render_totp(&state, &csrf, &db_conn, &user, Some("Invalid code"))
+            .await
+            .map(IntoResponse::into_response);
+    }
+
+    db::totp::disable(&db_conn, &user.id).await?;
+    audit::record(&db_conn, &client, AuditEvent::TotpDisabled, &user.id).await?;
+    tracing::info!(user = user.name, "Disabled two-factor authentication");
+    Ok(Redirect::to("/htm/totp").into_response())
+}
+
+/// Generates a new pending secret to be scanned and confirmed, replacing one that wasn't
+#[instrument(skip(state))]
+pub async fn post_totp_setup(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    DatabaseConnection(db_conn): DatabaseConnection,
+) -> Result<Redirect, AppError> {
+    if !user.totp_enabled {
+        let secret = totp::generate_secret();
+        let encrypted_secret = state.totp_cipher.encrypt(&user.id, &secret)?;
+        db::totp::set_pending_secret(&db_conn, &user.id, &encrypted_secret).await?;
+    }
+    Ok(Redirect::to("/htm/totp"))
+}
+
+/// Renders the enabled state, or the pending secret if one was set up
+async fn render_totp(
+    state: &AppState,
+    csrf: &CsrfToken,
+    db_conn: &PostgresPooledConnection,
+    user: &User,
+    error: Option<&str>,
+) -> RenderResult {
+    let pending_secret = if user.totp_enabled {
+        None
+    } else {
+        db::totp::get_secret(db_conn, &user.id).await?
+    };
+
+    let (otpauth_url, qr_code_svg) = match &pending_secret {
+        Some(encrypted_secret) => {
+            let secret = state.totp_cipher.decrypt(&user.id, encrypted_secret)?;
+            let totp = totp::totp(&state.config.totp, secret, &user.name)?;
+            (totp.get_url(), totp::qr_code_svg(&totp)?)
+        }
+        None => (String::new(), String::new()),
+    };
+
+    let template = TotpHtm {
+        csrf_token: &csrf.0,
+        enabled: user.totp_enabled,
+        pending: pending_secret.is_some(),
+        otpauth_url,
+        qr_code_svg,
+        error,
+    };
+    render(template)
+}
+
+async fn verify_second_factor(
+    state: &AppState,
+    db_conn: &PostgresPooledConnection,
+    user: &User,
+    code: &str,
+) -> Result<bool, AppError> {
+    let code = totp::normalize_code(code);
+    if !totp::is_code(&code) {
+        let code_hash = token::hash_token(&totp::normalize_recovery_code(&code));
+        let used = db::totp::use_recovery_code(db_conn, &user.id, &code_hash).await?;
+        if used {
+            tracing::info!(user = user.name, "Used a recovery code");
+        }
+        return Ok(used);
+    }
+
+    let Some(encrypted_secret) = db::totp::get_secret(db_conn, &user.id).await? else {
+        return Ok(false);
+    };
+
+    let secret = state.totp_cipher.decrypt(&user.id, &encrypted_secret)?;
+    let totp = totp::totp(&state.config.totp, secret, &user.name)?;
+    match totp::check_code(&totp, &code)? {
+        Some(step) => db::totp::accept_step(db_conn, &user.id, step).await,
+        None => Ok(false),
+    }
+}
@@ -0,0 +1,154 @@
+use crate::error::{self, AppError};
+use aws_sdk_sesv2::primitives::Blob;
+use aws_sdk_sesv2::types::{EmailContent, RawMessage};
+use chrono::Utc;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Deserialize;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use tower_http::BoxError;
+use uuid::Uuid;
+
+pub type SendFuture<'a> = Pin<Box<dyn Future<Output = Result<(), AppError>> + Send + 'a>>;
+
+#[derive(Clone, Deserialize)]
+pub struct MailConfig {
+    from: String,
+    /// Links in emails are built on top of this, it must be the public origin of the app
+    pub base_url: String,
+    transport: MailTransportConfig,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MailTransportConfig {
+    /// STARTTLS submission, the password is read from `smtp_password`
+    Smtp {
+        host: String,
+        port: u16,
+        username: Option<String>,
+    },
+    /// Uses the credentials and region of the Lambda environment
+    Ses,
+    /// Writes every message as an `.eml` file, for development
+    Directory { path: String },
+}
+
+/// Sends fully built messages. Implemented for each transport that can be chosen in
+/// [`MailConfig`].
+pub trait Mailer: Send + Sync {
+    fn send(&self, message: Message) -> SendFuture<'_>;
+}
+
+pub async fn mailer(
+    config: &MailConfig,
+    smtp_password: Option<String>,
+) -> Result<Arc<dyn Mailer>, BoxError> {
+    let mailer: Arc<dyn Mailer> = match &config.transport {
+        MailTransportConfig::Smtp {
+            host,
+            port,
+            username,
+        } => {
+            let mut builder =
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?.port(*port);
+            if let Some(username) = username {
+                let password = smtp_password.ok_or("smtp_password must be set")?;
+                builder = builder.credentials(Credentials::new(username.clone(), password));
+            }
+            Arc::new(SmtpMailer(builder.build()))
+        }
+        MailTransportConfig::Ses => {
+            let aws_config = aws_config::load_from_env().await;
+            Arc::new(SesMailer(aws_sdk_sesv2::Client::new(&aws_config)))
+        }
+        MailTransportConfig::Directory { path } => Arc::new(DirectoryMailer(PathBuf::from(path))),
+    };
+
+    Ok(mailer)
+}
+
+/// Builds a plain text message from the configured sender
+pub fn message(
+    config: &MailConfig,
+    to: &str,
+    subject: &str,
+    body: String,
+) -> Result<Message, AppError> {
+    let from = config
+        .from
+        .parse()
+        .map_err(|e| error::mail_error(format!("Invalid sender: {}", e)))?;
+    let to = to
+        .parse()
+        .map_err(|e| error::mail_error(format!("Invalid recipient: {}", e)))?;
+
+    Message::builder()
+        .from(from)
+        .to(to)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body)
+        .map_err(|e| error::mail_error(e.to_string()))
+}
+
+struct SmtpMailer(AsyncSmtpTransport<Tokio1Executor>);
+
+impl Mailer for SmtpMailer {
+    fn send(&self, message: Message) -> SendFuture<'_> {
+        Box::pin(async move {
+            self.0
+                .send(message)
+                .await
+                .map_err(|e| error::mail_error(e.to_string()))?;
+            Ok(())
+        })
+    }
+}
+
+struct SesMailer(aws_sdk_sesv2::Client);
+
+impl Mailer for SesMailer {
+    fn send(&self, message: Message) -> SendFuture<'_> {
+        Box::pin(async move {
+            let raw = RawMessage::builder()
+                .data(Blob::new(message.formatted()))
+                .build()
+                .map_err(|e| error::mail_error(e.to_string()))?;
+
+            self.0
+                .send_email()
+                .content(EmailContent::builder().raw(raw).build())
+                .send()
+                .await
+                .map_err(|e| error::mail_error(e.to_string()))?;
+            Ok(())
+        })
+    }
+}
+
+struct DirectoryMailer(PathBuf);
+
+impl Mailer for DirectoryMailer {
+    fn send(&self, message: Message) -> SendFuture<'_> {
+        Box::pin(async move {
+            let file_name = format!(
+                "{}-{}.eml",
+                Utc::now().format("%Y%m%dT%H%M%S"),
+                Uuid::now_v7()
+            );
+
+            tokio::fs::create_dir_all(&self.0)
+                .await
+                .map_err(|e| error::mail_error(e.to_string()))?;
+            tokio::fs::write(self.0.join(file_name), message.formatted())
+                .await
+                .map_err(|e| error::mail_error(e.to_string()))?;
+            Ok(())
+        })
+    }
+}
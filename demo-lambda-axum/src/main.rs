@@ -1,44 +1,101 @@
+mod access_token;
 mod api;
+mod audit;
+mod cookie_keys;
+mod csrf;
 mod db;
 mod error;
 mod extract;
 mod health;
 mod htm;
+mod mail;
+mod markdown;
+mod oidc;
+mod passkey;
+mod password;
 mod serde_decorators;
 mod session;
+mod throttle;
+mod token;
+mod totp;
+mod verification;
 
+use crate::cookie_keys::{CookieKeys, cookie_key_middleware};
+use crate::csrf::csrf_middleware;
+use crate::db::users::Role;
 use crate::db::{PostgresPool, postgres_pool};
-use crate::htm::{journal, login};
-use crate::session::session_middleware;
+use crate::htm::password_reset::PasswordResetConfig;
+use crate::htm::register::RegistrationConfig;
+use crate::htm::settings::AccountDeletionConfig;
+use crate::htm::{
+    access_tokens, admin, audit as audit_htm, email, journal, login, oidc as oidc_htm, passkeys,
+    password_reset, register, search, sessions, settings, tags as tags_htm, totp as totp_htm,
+};
+use crate::mail::{MailConfig, Mailer};
+use crate::oidc::{OidcConfig, OidcProvider};
+use crate::passkey::WebauthnConfig;
+use crate::password::PasswordHashConfig;
+use crate::session::{SessionConfig, require_role, session_middleware};
+use crate::throttle::LoginThrottleConfig;
+use crate::totp::{SecretCipher, TotpConfig};
+use crate::verification::{EmailVerificationConfig, EmailVerifier};
 use axum::Router;
 use axum::extract::Request;
 use axum::middleware::{self, Next};
 use axum::response::{Redirect, Response};
 use axum::routing::{delete, get, post};
-use axum_extra::extract::cookie::Key;
-use base64::Engine;
-use base64::prelude::BASE64_STANDARD;
 use chrono::Utc;
 use dotenvy::dotenv;
 use lambda_http::run;
 use serde::Deserialize;
+use std::sync::Arc;
 use tower_http::BoxError;
 use tower_http::services::ServeDir;
 use util::config::load_app_config;
 use util::tracing;
+use webauthn_rs::Webauthn;
 
 #[derive(Clone, Deserialize)]
 struct AppConfig {
     ca_certs: String,
     postgres: String,
-    cookie_key_base64: String,
+    /// The first key encrypts new cookies, the others are still accepted while rotating keys.
+    /// Deployments still setting the single key of `cookie_key_base64` keep working.
+    #[serde(
+        alias = "cookie_key_base64",
+        deserialize_with = "serde_decorators::comma_separated"
+    )]
+    cookie_keys_base64: Vec<String>,
+    totp_key_base64: String,
+    verification_key_base64: String,
+    #[serde(default)]
+    oidc_client_secret: Option<String>,
+    #[serde(default)]
+    smtp_password: Option<String>,
+    password_hash: PasswordHashConfig,
+    registration: RegistrationConfig,
+    session: SessionConfig,
+    login_throttle: LoginThrottleConfig,
+    totp: TotpConfig,
+    webauthn: WebauthnConfig,
+    oidc: OidcConfig,
+    mail: MailConfig,
+    password_reset: PasswordResetConfig,
+    account_deletion: AccountDeletionConfig,
+    email_verification: EmailVerificationConfig,
 }
 
 #[derive(Clone)]
 struct AppState {
     config: AppConfig,
     postgres_pool: PostgresPool,
-    cookie_key: Key,
+    cookie_keys: CookieKeys,
+    totp_cipher: SecretCipher,
+    webauthn: Arc<Webauthn>,
+    /// Set when OpenID Connect login is enabled
+    oidc: Option<OidcProvider>,
+    mailer: Arc<dyn Mailer>,
+    email_verifier: EmailVerifier,
 }
 
 #[tokio::main]
@@ -52,11 +109,21 @@ async fn main() -> Result<(), BoxError> {
     let state = AppState {
         config,
         postgres_pool: postgres_pool(&shared_config).await?,
-        cookie_key: Key::from(
-            &*BASE64_STANDARD
-                .decode(&shared_config.cookie_key_base64)
-                .unwrap(),
-        ),
+        cookie_keys: CookieKeys::from_base64(&shared_config.cookie_keys_base64)?,
+        totp_cipher: SecretCipher::from_base64(&shared_config.totp_key_base64)?,
+        webauthn: passkey::webauthn(&shared_config.webauthn)?,
+        oidc: shared_config
+            .oidc
+            .enabled
+            .then(|| {
+                OidcProvider::new(
+                    &shared_config.oidc,
+                    shared_config.oidc_client_secret.clone(),
+                )
+            })
+            .transpose()?,
+        mailer: mail::mailer(&shared_config.mail, shared_config.smtp_password.clone()).await?,
+        email_verifier: EmailVerifier::from_base64(&shared_config.verification_key_base64)?,
     };
 
     let app = Router::new()
@@ -66,32 +133,118 @@ async fn main() -> Result<(), BoxError> {
             "/api/v1",
             Router::new()
                 .route("/hello", get(api::get_hello))
-                .route("/error", get(api::get_error)),
+                .route("/error", get(api::get_error))
+                .route("/entries/{date}", get(api::entries::get_entries))
+                .route("/entries/{date}", post(api::entries::post_entry))
+                .route("/entries/{date}/{id}", delete(api::entries::delete_entry))
+                .route("/search", get(api::entries::get_search)),
         )
         .nest(
             "/htm",
             Router::new()
                 .route("/login", get(login::get_login))
                 .route("/login", post(login::post_login))
+                .route("/login/oidc", get(oidc_htm::get_oidc_login))
+                .route("/login/oidc/callback", get(oidc_htm::get_oidc_callback))
+                .route("/login/totp", get(totp_htm::get_login_totp))
+                .route("/login/totp", post(totp_htm::post_login_totp))
+                .route("/logout", post(login::post_logout))
+                .route("/logout/all", post(login::post_logout_all))
+                .route("/register", get(register::get_register))
+                .route("/register", post(register::post_register))
+                .route("/email", get(email::get_email))
+                .route("/email", post(email::post_email))
+                .route("/email/resend", post(email::post_email_resend))
+                .route("/email/verify", get(email::get_email_verify))
+                .route("/password/forgot", get(password_reset::get_forgot_password))
+                .route(
+                    "/password/forgot",
+                    post(password_reset::post_forgot_password),
+                )
+                .route("/password/reset", get(password_reset::get_reset_password))
+                .route("/password/reset", post(password_reset::post_reset_password))
+                .route("/totp", get(totp_htm::get_totp))
+                .route("/totp/setup", post(totp_htm::post_totp_setup))
+                .route("/totp/enable", post(totp_htm::post_totp_enable))
+                .route("/totp/disable", post(totp_htm::post_totp_disable))
+                .nest(
+                    "/passkeys",
+                    Router::new()
+                        .route("/", get(passkeys::get_passkeys))
+                        .route("/{id}", delete(passkeys::delete_passkey))
+                        .route("/register/start", post(passkeys::post_registration_start))
+                        .route("/register/finish", post(passkeys::post_registration_finish))
+                        .route("/login/start", post(passkeys::post_login_start))
+                        .route("/login/finish", post(passkeys::post_login_finish)),
+                )
+                .route("/tokens", get(access_tokens::get_access_tokens))
+                .route("/tokens", post(access_tokens::post_access_token))
+                .route("/tokens/{id}", delete(access_tokens::delete_access_token))
+                .route("/sessions", get(sessions::get_sessions))
+                .route("/sessions/{id}", delete(sessions::delete_session))
+                .route("/activity", get(audit_htm::get_activity))
+                .route("/settings", get(settings::get_settings))
+                .route("/settings/password", post(settings::post_password))
+                .route("/settings/username", post(settings::post_username))
+                .route("/settings/export", get(settings::get_export))
+                .route("/settings/delete", post(settings::post_delete_account))
+                .route(
+                    "/settings/reauthenticate",
+                    post(oidc_htm::post_oidc_reauthenticate),
+                )
+                .route(
+                    "/settings/delete/cancel",
+                    post(settings::post_cancel_deletion),
+                )
+                .nest(
+                    "/admin",
+                    Router::new()
+                        .route("/", get(admin::get_admin))
+                        .route("/users/{id}/disable", post(admin::post_disable_user))
+                        .route("/users/{id}/enable", post(admin::post_enable_user))
+                        .route("/invite-codes", post(admin::post_invite_code))
+                        .route("/audit", get(audit_htm::get_admin_audit))
+                        .route_layer(middleware::from_fn_with_state(Role::Admin, require_role)),
+                )
+                .route("/search", get(search::get_search))
+                .route("/tags/{tag}", get(tags_htm::get_tag))
                 .route("/index", get(redirect_to_index_with_date))
                 .route("/index/{date}", get(journal::get_index))
                 .nest(
                     "/journal",
                     Router::new()
+                        .route("/preview", post(journal::post_preview))
                         .route("/entries/{date}", get(journal::get_journal_entries))
                         .route("/entries/{date}", post(journal::update_journal_entry))
+                        .route(
+                            "/entries/{date}/order",
+                            post(journal::reorder_journal_entries),
+                        )
+                        .route("/entries/{date}/{id}", get(journal::get_journal_entry))
                         .route(
                             "/entries/{date}/{id}",
                             delete(journal::delete_journal_entry),
+                        )
+                        .route(
+                            "/entries/{date}/{id}/edit",
+                            get(journal::get_journal_entry_edit),
                         ),
                 ),
         )
         .nest_service("/static", ServeDir::new("static"))
         .layer(middleware::from_fn(request_log_middleware))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            csrf_middleware,
+        ))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             session_middleware,
         ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            cookie_key_middleware,
+        ))
         .with_state(state);
 
     run(app).await
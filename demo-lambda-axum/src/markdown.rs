@@ -0,0 +1,93 @@
+use ammonia::Builder;
+use pulldown_cmark::{Event, Parser, html};
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+/// The only tags and attributes that survive sanitizing, enough for CommonMark without raw HTML
+static SANITIZER: LazyLock<Builder<'static>> = LazyLock::new(|| {
+    let mut builder = Builder::empty();
+    builder
+        .tags(HashSet::from([
+            "a",
+            "blockquote",
+            "br",
+            "code",
+            "em",
+            "h1",
+            "h2",
+            "h3",
+            "h4",
+            "h5",
+            "h6",
+            "hr",
+            "li",
+            "ol",
+            "p",
+            "pre",
+            "strong",
+            "ul",
+        ]))
+        .add_tag_attributes("a", ["href"])
+        .add_tag_attributes("ol", ["start"])
+        .url_schemes(HashSet::from(["http", "https", "mailto"]))
+        .link_rel(Some("noopener noreferrer nofollow"));
+    builder
+});
+
+/// Renders the CommonMark of an entry to sanitized HTML. HTML written in the entry is shown as
+/// text, like before entries were Markdown.
+pub fn to_html(content: &str) -> String {
+    let events = Parser::new(content).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        event => event,
+    });
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events);
+    SANITIZER.clean(&unsafe_html).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_html_renders_plaintext_as_a_paragraph() {
+        assert_eq!(to_html("Just some text"), "<p>Just some text</p>\n");
+    }
+
+    #[test]
+    fn to_html_renders_markdown() {
+        assert_eq!(
+            to_html("# Title\n\n*one* **two** `three`"),
+            "<h1>Title</h1>\n<p><em>one</em> <strong>two</strong> <code>three</code></p>\n"
+        );
+    }
+
+    #[test]
+    fn to_html_shows_raw_html_as_text() {
+        assert_eq!(
+            to_html("<script>alert(1)</script>"),
+            "&lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+        assert_eq!(
+            to_html("Hi <img src=x onerror=alert(1)>"),
+            "<p>Hi &lt;img src=x onerror=alert(1)&gt;</p>\n"
+        );
+    }
+
+    #[test]
+    fn to_html_strips_javascript_links() {
+        let html = to_html("[click](javascript:alert(1))");
+        assert!(!html.contains("javascript"), "{}", html);
+        assert!(html.contains("click"));
+    }
+
+    #[test]
+    fn to_html_keeps_safe_links() {
+        assert_eq!(
+            to_html("[docs](https://example.com)"),
+            "<p><a href=\"https://example.com\" rel=\"noopener noreferrer nofollow\">docs</a></p>\n"
+        );
+    }
+}
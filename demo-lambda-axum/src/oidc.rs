@@ -0,0 +1,411 @@
+use crate::error::{self, AppError};
+use chrono::{DateTime, Utc};
+use openidconnect::core::{
+    CoreAuthPrompt, CoreAuthenticationFlow, CoreClient, CoreProviderMetadata,
+};
+use openidconnect::reqwest;
+use openidconnect::{
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken as OidcState, EndpointMaybeSet,
+    EndpointNotSet, EndpointSet, IssuerUrl, Nonce, PkceCodeChallenge, PkceCodeVerifier,
+    RedirectUrl, Scope,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tower_http::BoxError;
+use uuid::Uuid;
+
+/// A client built from discovered metadata, which may or may not list the token and userinfo
+/// endpoints
+type DiscoveredClient = CoreClient<
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointMaybeSet,
+    EndpointMaybeSet,
+>;
+
+#[derive(Clone, Deserialize)]
+pub struct OidcConfig {
+    pub enabled: bool,
+    /// Shown on the login page as "Sign in with ..."
+    pub provider_name: String,
+    issuer_url: String,
+    client_id: String,
+    /// Must point to `/htm/login/oidc/callback` and be registered at the provider
+    redirect_url: String,
+    /// Links the first login of an identity to an existing user whose verified email matches the
+    /// `email` claim, if the provider marks it as verified. Only safe if the provider really
+    /// verifies the addresses it asserts.
+    #[serde(default)]
+    pub link_existing_users: bool,
+}
+
+/// The secrets of an authorization request, kept in a private cookie until the callback
+#[derive(Serialize, Deserialize)]
+pub struct OidcLoginState {
+    pub state: String,
+    nonce: String,
+    pkce_verifier: String,
+    /// Set when the user of this session confirms their identity instead of logging in
+    #[serde(default)]
+    pub reauthenticated_session: Option<Uuid>,
+}
+
+/// The verified claims of an ID token
+#[derive(Debug)]
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub preferred_username: Option<String>,
+    /// The `email` claim, only if the provider has verified it
+    pub verified_email: Option<String>,
+    /// When the user last entered their credentials at the provider
+    pub auth_time: Option<DateTime<Utc>>,
+}
+
+/// The provider metadata is discovered on first use and kept for the lifetime of the Lambda
+/// instance, together with the signing keys it references.
+#[derive(Clone)]
+pub struct OidcProvider {
+    issuer_url: IssuerUrl,
+    redirect_url: RedirectUrl,
+    client_id: ClientId,
+    client_secret: Option<ClientSecret>,
+    http_client: reqwest::Client,
+    metadata: Arc<OnceCell<CoreProviderMetadata>>,
+}
+
+impl OidcProvider {
+    pub fn new(config: &OidcConfig, client_secret: Option<String>) -> Result<Self, BoxError> {
+        let issuer_url = IssuerUrl::new(config.issuer_url.clone())
+            .map_err(|e| format!("oidc.issuer_url is not a valid URL: {}", e))?;
+        let redirect_url = RedirectUrl::new(config.redirect_url.clone())
+            .map_err(|e| format!("oidc.redirect_url is not a valid URL: {}", e))?;
+
+        let http_client = reqwest::ClientBuilder::new()
+            // following redirects would open the token exchange up to SSRF
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+
+        Ok(OidcProvider {
+            issuer_url,
+            redirect_url,
+            client_id: ClientId::new(config.client_id.clone()),
+            client_secret: client_secret.map(ClientSecret::new),
+            http_client,
+            metadata: Arc::new(OnceCell::new()),
+        })
+    }
+
+    /// Returns the URL to send the user to, and the state to check the callback against
+    pub async fn authorization_request(&self) -> Result<(String, OidcLoginState), AppError> {
+        self.request(None).await
+    }
+
+    /// Like [`Self::authorization_request`], but asks the provider to have the user of `session_id`
+    /// log in again, even if they still have a session there
+    pub async fn reauthentication_request(
+        &self,
+        session_id: &Uuid,
+    ) -> Result<(String, OidcLoginState), AppError> {
+        self.request(Some(*session_id)).await
+    }
+
+    async fn request(
+        &self,
+        reauthenticated_session: Option<Uuid>,
+    ) -> Result<(String, OidcLoginState), AppError> {
+        let client = self.client().await?;
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+        let mut request = client
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                OidcState::new_random,
+                Nonce::new_random,
+            )
+            .add_scope(Scope::new("profile".to_owned()))
+            .add_scope(Scope::new("email".to_owned()))
+            .set_pkce_challenge(pkce_challenge);
+        if reauthenticated_session.is_some() {
+            request = request
+                .add_prompt(CoreAuthPrompt::Login)
+                .set_max_age(Duration::ZERO);
+        }
+        let (url, state, nonce) = request.url();
+
+        let login_state = OidcLoginState {
+            state: state.into_secret(),
+            nonce: nonce.secret().clone(),
+            pkce_verifier: pkce_verifier.into_secret(),
+            reauthenticated_session,
+        };
+        Ok((url.to_string(), login_state))
+    }
+
+    /// Exchanges the authorization code and verifies the signature, audience, expiry and nonce of
+    /// the returned ID token
+    pub async fn exchange_code(
+        &self,
+        code: String,
+        login_state: OidcLoginState,
+    ) -> Result<OidcIdentity, AppError> {
+        let client = self.client().await?;
+
+        let token_response = client
+            .exchange_code(AuthorizationCode::new(code))
+            .map_err(|e| error::oidc_error(e.to_string()))?
+            .set_pkce_verifier(PkceCodeVerifier::new(login_state.pkce_verifier))
+            .request_async(&self.http_client)
+            .await
+            .map_err(|e| error::oidc_error(format!("Token request failed: {}", e)))?;
+
+        let id_token = token_response
+            .extra_fields()
+            .id_token()
+            .ok_or_else(|| error::oidc_error("The provider returned no ID token".to_owned()))?;
+        let claims = id_token
+            .claims(&client.id_token_verifier(), &Nonce::new(login_state.nonce))
+            .map_err(|e| error::oidc_error(format!("Invalid ID token: {}", e)))?;
+
+        Ok(OidcIdentity {
+            issuer: claims.issuer().to_string(),
+            subject: claims.subject().to_string(),
+            preferred_username: claims
+                .preferred_username()
+                .map(|username| username.to_string()),
+            verified_email: claims
+                .email()
+                .filter(|_| claims.email_verified() == Some(true))
+                .map(|email| email.to_string()),
+            auth_time: claims.auth_time(),
+        })
+    }
+
+    async fn client(&self) -> Result<DiscoveredClient, AppError> {
+        let metadata = self
+            .metadata
+            .get_or_try_init(|| {
+                CoreProviderMetadata::discover_async(self.issuer_url.clone(), &self.http_client)
+            })
+            .await
+            .map_err(|e| error::oidc_error(format!("Discovery failed: {}", e)))?;
+
+        Ok(CoreClient::from_provider_metadata(
+            metadata.clone(),
+            self.client_id.clone(),
+            self.client_secret.clone(),
+        )
+        .set_redirect_uri(self.redirect_url.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::routing::{get, post};
+    use axum::{Form, Json, Router};
+    use base64::Engine;
+    use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::sign::Signer;
+    use serde_json::{Value, json};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use tokio::net::TcpListener;
+
+    const CLIENT_ID: &str = "journal";
+    const CODE: &str = "authorization-code";
+
+    /// An issuer serving discovery, its signing keys and a token endpoint that returns whatever
+    /// ID token the test put in `id_token`
+    #[derive(Clone)]
+    struct MockIssuer {
+        url: String,
+        key: PKey<Private>,
+        id_token: Arc<Mutex<String>>,
+    }
+
+    impl MockIssuer {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let issuer = MockIssuer {
+                url: format!("http://{}", listener.local_addr().unwrap()),
+                key: PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap(),
+                id_token: Arc::new(Mutex::new(String::new())),
+            };
+
+            let app = Router::new()
+                .route("/.well-known/openid-configuration", get(discovery))
+                .route("/jwks", get(jwks))
+                .route("/token", post(token))
+                .with_state(issuer.clone());
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+            issuer
+        }
+
+        fn provider(&self) -> OidcProvider {
+            let config = OidcConfig {
+                enabled: true,
+                provider_name: "Mock".to_owned(),
+                issuer_url: self.url.clone(),
+                client_id: CLIENT_ID.to_owned(),
+                redirect_url: "https://journal.example.com/htm/login/oidc/callback".to_owned(),
+                link_existing_users: false,
+            };
+            OidcProvider::new(&config, None).unwrap()
+        }
+
+        /// Signs the ID token the token endpoint returns next
+        fn issue_id_token(&self, nonce: &str) {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            let header = json!({ "alg": "RS256", "kid": "test", "typ": "JWT" });
+            let claims = json!({
+                "iss": self.url,
+                "sub": "subject-1",
+                "aud": CLIENT_ID,
+                "iat": now,
+                "exp": now + 300,
+                "nonce": nonce,
+                "auth_time": now,
+                "preferred_username": "alice",
+                "email": "alice@example.com",
+                "email_verified": true,
+            });
+            let payload = format!(
+                "{}.{}",
+                BASE64_URL_SAFE_NO_PAD.encode(header.to_string()),
+                BASE64_URL_SAFE_NO_PAD.encode(claims.to_string())
+            );
+
+            let mut signer = Signer::new(MessageDigest::sha256(), &self.key).unwrap();
+            signer.update(payload.as_bytes()).unwrap();
+            let signature = BASE64_URL_SAFE_NO_PAD.encode(signer.sign_to_vec().unwrap());
+
+            *self.id_token.lock().unwrap() = format!("{}.{}", payload, signature);
+        }
+    }
+
+    async fn discovery(State(issuer): State<MockIssuer>) -> Json<Value> {
+        Json(json!({
+            "issuer": issuer.url,
+            "authorization_endpoint": format!("{}/authorize", issuer.url),
+            "token_endpoint": format!("{}/token", issuer.url),
+            "jwks_uri": format!("{}/jwks", issuer.url),
+            "response_types_supported": ["code"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": ["RS256"],
+        }))
+    }
+
+    async fn jwks(State(issuer): State<MockIssuer>) -> Json<Value> {
+        let rsa = issuer.key.rsa().unwrap();
+        Json(json!({
+            "keys": [{
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "kid": "test",
+                "n": BASE64_URL_SAFE_NO_PAD.encode(rsa.n().to_vec()),
+                "e": BASE64_URL_SAFE_NO_PAD.encode(rsa.e().to_vec()),
+            }]
+        }))
+    }
+
+    async fn token(
+        State(issuer): State<MockIssuer>,
+        Form(params): Form<HashMap<String, String>>,
+    ) -> Json<Value> {
+        assert_eq!(params["grant_type"], "authorization_code");
+        assert_eq!(params["code"], CODE);
+        assert!(params.contains_key("code_verifier"));
+
+        Json(json!({
+            "access_token": "access-token",
+            "token_type": "Bearer",
+            "expires_in": 300,
+            "id_token": *issuer.id_token.lock().unwrap(),
+        }))
+    }
+
+    fn query_param(url: &str, param: &str) -> Option<String> {
+        let url = openidconnect::url::Url::parse(url).unwrap();
+        url.query_pairs()
+            .find(|(name, _)| name == param)
+            .map(|(_, value)| value.into_owned())
+    }
+
+    fn nonce(url: &str) -> String {
+        query_param(url, "nonce").unwrap()
+    }
+
+    #[tokio::test]
+    async fn exchange_code_returns_the_verified_identity() {
+        let issuer = MockIssuer::start().await;
+        let provider = issuer.provider();
+
+        let (url, login_state) = provider.authorization_request().await.unwrap();
+        assert!(url.starts_with(&format!("{}/authorize?", issuer.url)));
+        issuer.issue_id_token(&nonce(&url));
+
+        let identity = provider
+            .exchange_code(CODE.to_owned(), login_state)
+            .await
+            .unwrap();
+        assert_eq!(identity.issuer, issuer.url);
+        assert_eq!(identity.subject, "subject-1");
+        assert_eq!(identity.preferred_username.as_deref(), Some("alice"));
+        assert_eq!(
+            identity.verified_email.as_deref(),
+            Some("alice@example.com")
+        );
+        assert!(identity.auth_time.is_some());
+    }
+
+    #[tokio::test]
+    async fn exchange_code_rejects_an_id_token_for_another_request() {
+        let issuer = MockIssuer::start().await;
+        let provider = issuer.provider();
+
+        let (url, _) = provider.authorization_request().await.unwrap();
+        let (_, other_login_state) = provider.authorization_request().await.unwrap();
+        issuer.issue_id_token(&nonce(&url));
+
+        assert!(
+            provider
+                .exchange_code(CODE.to_owned(), other_login_state)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn reauthentication_requires_a_fresh_login() {
+        let issuer = MockIssuer::start().await;
+        let provider = issuer.provider();
+        let session_id = Uuid::new_v4();
+
+        let (url, login_state) = provider.authorization_request().await.unwrap();
+        assert_eq!(query_param(&url, "prompt"), None);
+        assert_eq!(login_state.reauthenticated_session, None);
+
+        let (url, login_state) = provider
+            .reauthentication_request(&session_id)
+            .await
+            .unwrap();
+        assert_eq!(query_param(&url, "prompt").as_deref(), Some("login"));
+        assert_eq!(query_param(&url, "max_age").as_deref(), Some("0"));
+        assert_eq!(login_state.reauthenticated_session, Some(session_id));
+    }
+}
@@ -0,0 +1,287 @@
+use crate::error::{self, AppError};
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+use std::sync::Arc;
+use tower_http::BoxError;
+use webauthn_rs::fake::{FakePasskeyDistribution, WebauthnFakeCredentialGenerator};
+use webauthn_rs::prelude::{RequestChallengeResponse, Url};
+use webauthn_rs::{DEFAULT_AUTHENTICATOR_TIMEOUT, Webauthn, WebauthnBuilder};
+
+#[derive(Clone, Deserialize)]
+pub struct WebauthnConfig {
+    /// The domain passkeys are bound to, must be the origin's host or a parent domain of it
+    rp_id: String,
+    rp_origin: String,
+    rp_name: String,
+}
+
+pub fn webauthn(config: &WebauthnConfig) -> Result<Arc<Webauthn>, BoxError> {
+    let rp_origin = Url::parse(&config.rp_origin)
+        .map_err(|e| format!("webauthn.rp_origin is not a valid URL: {}", e))?;
+
+    let webauthn = WebauthnBuilder::new(&config.rp_id, &rp_origin)?
+        .rp_name(&config.rp_name)
+        .build()?;
+
+    Ok(Arc::new(webauthn))
+}
+
+/// Builds a challenge for a username that has no passkeys, so that the answer doesn't tell whether
+/// the account exists. The credential ids are derived from the username and `secret`, asking twice
+/// returns the same ones. Like real accounts, some usernames get none and `None` is returned.
+pub fn decoy_challenge(
+    config: &WebauthnConfig,
+    secret: &[u8],
+    username: &str,
+) -> Result<Option<RequestChallengeResponse>, AppError> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(b"passkey decoys");
+    let hmac_key = mac.finalize().into_bytes();
+
+    let credential_ids =
+        WebauthnFakeCredentialGenerator::<FakePasskeyDistribution>::new(&hmac_key)?
+            .generate(username.as_bytes())?;
+    if credential_ids.is_empty() {
+        return Ok(None);
+    }
+
+    let allow_credentials: Vec<_> = credential_ids
+        .iter()
+        .map(|id| json!({ "type": "public-key", "id": id }))
+        .collect();
+    let challenge = json!({
+        "publicKey": {
+            "challenge": BASE64_URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>()),
+            "timeout": DEFAULT_AUTHENTICATOR_TIMEOUT.as_millis(),
+            "rpId": config.rp_id,
+            "allowCredentials": allow_credentials,
+            "userVerification": "required",
+        }
+    });
+
+    serde_json::from_value(challenge)
+        .map(Some)
+        .map_err(|e| error::passkey_error(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::bn::{BigNum, BigNumContext};
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::sign::Signer;
+    use serde_json::Value;
+    use sha2::Digest;
+    use webauthn_rs::prelude::{PublicKeyCredential, RegisterPublicKeyCredential, Uuid};
+
+    const ORIGIN: &str = "https://journal.example.com";
+
+    fn config() -> WebauthnConfig {
+        WebauthnConfig {
+            rp_id: "journal.example.com".to_owned(),
+            rp_origin: ORIGIN.to_owned(),
+            rp_name: "Journal".to_owned(),
+        }
+    }
+
+    /// A software authenticator with a single P-256 credential and "none" attestation
+    struct SoftAuthenticator {
+        key: PKey<Private>,
+        credential_id: Vec<u8>,
+        counter: u32,
+    }
+
+    impl SoftAuthenticator {
+        fn new() -> Self {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            SoftAuthenticator {
+                key: PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap(),
+                credential_id: rand::random::<[u8; 16]>().to_vec(),
+                counter: 0,
+            }
+        }
+
+        fn register(&mut self, challenge: &Value) -> RegisterPublicKeyCredential {
+            let client_data = client_data("webauthn.create", challenge);
+
+            let mut auth_data = self.auth_data(0x45);
+            auth_data.extend([0; 16]);
+            auth_data.extend((self.credential_id.len() as u16).to_be_bytes());
+            auth_data.extend(&self.credential_id);
+            auth_data.extend(self.cose_key());
+
+            let mut attestation = vec![0xa3];
+            attestation.extend(cbor_text("fmt"));
+            attestation.extend(cbor_text("none"));
+            attestation.extend(cbor_text("attStmt"));
+            attestation.push(0xa0);
+            attestation.extend(cbor_text("authData"));
+            attestation.extend(cbor_bytes(&auth_data));
+
+            serde_json::from_value(json!({
+                "id": base64(&self.credential_id),
+                "rawId": base64(&self.credential_id),
+                "type": "public-key",
+                "response": {
+                    "attestationObject": base64(&attestation),
+                    "clientDataJSON": base64(&client_data),
+                },
+            }))
+            .unwrap()
+        }
+
+        fn sign(&mut self, challenge: &Value) -> PublicKeyCredential {
+            let client_data = client_data("webauthn.get", challenge);
+            self.counter += 1;
+            let auth_data = self.auth_data(0x05);
+
+            let mut signer = Signer::new(MessageDigest::sha256(), &self.key).unwrap();
+            signer.update(&auth_data).unwrap();
+            signer.update(&Sha256::digest(&client_data)).unwrap();
+            let signature = signer.sign_to_vec().unwrap();
+
+            serde_json::from_value(json!({
+                "id": base64(&self.credential_id),
+                "rawId": base64(&self.credential_id),
+                "type": "public-key",
+                "response": {
+                    "authenticatorData": base64(&auth_data),
+                    "clientDataJSON": base64(&client_data),
+                    "signature": base64(&signature),
+                    "userHandle": null,
+                },
+            }))
+            .unwrap()
+        }
+
+        fn auth_data(&self, flags: u8) -> Vec<u8> {
+            let mut auth_data = Sha256::digest(config().rp_id.as_bytes()).to_vec();
+            auth_data.push(flags);
+            auth_data.extend(self.counter.to_be_bytes());
+            auth_data
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let ec_key = self.key.ec_key().unwrap();
+            let mut ctx = BigNumContext::new().unwrap();
+            let (mut x, mut y) = (BigNum::new().unwrap(), BigNum::new().unwrap());
+            ec_key
+                .public_key()
+                .affine_coordinates(ec_key.group(), &mut x, &mut y, &mut ctx)
+                .unwrap();
+
+            // {1: 2 (EC2), 3: -7 (ES256), -1: 1 (P-256), -2: x, -3: y}
+            let mut cose_key = vec![0xa5, 0x01, 0x02, 0x03, 0x26, 0x20, 0x01, 0x21];
+            cose_key.extend(cbor_bytes(&x.to_vec_padded(32).unwrap()));
+            cose_key.push(0x22);
+            cose_key.extend(cbor_bytes(&y.to_vec_padded(32).unwrap()));
+            cose_key
+        }
+    }
+
+    fn client_data(kind: &str, challenge: &Value) -> Vec<u8> {
+        let challenge = challenge["publicKey"]["challenge"].as_str().unwrap();
+        json!({ "type": kind, "challenge": challenge, "origin": ORIGIN, "crossOrigin": false })
+            .to_string()
+            .into_bytes()
+    }
+
+    fn base64(bytes: &[u8]) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    fn cbor_text(text: &str) -> Vec<u8> {
+        let mut cbor = vec![0x60 | text.len() as u8];
+        cbor.extend(text.as_bytes());
+        cbor
+    }
+
+    fn cbor_bytes(bytes: &[u8]) -> Vec<u8> {
+        let mut cbor = match bytes.len() {
+            len @ ..24 => vec![0x40 | len as u8],
+            len @ ..256 => vec![0x58, len as u8],
+            len => [vec![0x59], (len as u16).to_be_bytes().to_vec()].concat(),
+        };
+        cbor.extend(bytes);
+        cbor
+    }
+
+    #[test]
+    fn passkeys_register_and_sign_in() {
+        let webauthn = webauthn(&config()).unwrap();
+        let mut authenticator = SoftAuthenticator::new();
+
+        let (challenge, registration) = webauthn
+            .start_passkey_registration(Uuid::new_v4(), "alice", "alice", None)
+            .unwrap();
+        let credential = authenticator.register(&serde_json::to_value(challenge).unwrap());
+        let passkey = webauthn
+            .finish_passkey_registration(&credential, &registration)
+            .unwrap();
+
+        let (challenge, authentication) = webauthn
+            .start_passkey_authentication(std::slice::from_ref(&passkey))
+            .unwrap();
+        let challenge = serde_json::to_value(challenge).unwrap();
+        assert_eq!(
+            challenge["publicKey"]["allowCredentials"][0]["id"],
+            base64(&authenticator.credential_id)
+        );
+        let credential = authenticator.sign(&challenge);
+        let result = webauthn
+            .finish_passkey_authentication(&credential, &authentication)
+            .unwrap();
+        assert_eq!(result.cred_id(), passkey.cred_id());
+        assert_eq!(result.counter(), 1);
+    }
+
+    #[test]
+    fn passkeys_reject_an_assertion_for_another_challenge() {
+        let webauthn = webauthn(&config()).unwrap();
+        let mut authenticator = SoftAuthenticator::new();
+
+        let (challenge, registration) = webauthn
+            .start_passkey_registration(Uuid::new_v4(), "alice", "alice", None)
+            .unwrap();
+        let credential = authenticator.register(&serde_json::to_value(challenge).unwrap());
+        let passkey = webauthn
+            .finish_passkey_registration(&credential, &registration)
+            .unwrap();
+
+        let (_, authentication) = webauthn
+            .start_passkey_authentication(std::slice::from_ref(&passkey))
+            .unwrap();
+        let (other_challenge, _) = webauthn.start_passkey_authentication(&[passkey]).unwrap();
+        let credential = authenticator.sign(&serde_json::to_value(other_challenge).unwrap());
+
+        assert!(
+            webauthn
+                .finish_passkey_authentication(&credential, &authentication)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn decoy_challenges_are_stable_per_username() {
+        let decoy_ids = |username: &str| {
+            decoy_challenge(&config(), b"secret", username)
+                .unwrap()
+                .map(|challenge| serde_json::to_value(challenge).unwrap())
+                .map(|challenge| challenge["publicKey"]["allowCredentials"].clone())
+        };
+
+        let with_decoys = (0..100)
+            .map(|n| format!("user{}", n))
+            .find(|username| decoy_ids(username).is_some())
+            .unwrap();
+        assert_eq!(decoy_ids(&with_decoys), decoy_ids(&with_decoys));
+        assert_ne!(decoy_ids(&with_decoys), decoy_ids("someone-else"));
+    }
+}
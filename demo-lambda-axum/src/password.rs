@@ -0,0 +1,124 @@
+use crate::error::AppError;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+
+#[derive(Clone, Deserialize)]
+pub struct PasswordHashConfig {
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+pub enum PasswordVerification {
+    Valid,
+    /// The password matched, but the stored value is legacy plaintext or uses outdated parameters
+    ValidNeedsRehash,
+    Invalid,
+}
+
+pub fn hash_password(config: &PasswordHashConfig, password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = argon2(config)?.hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(
+    config: &PasswordHashConfig,
+    password: &str,
+    stored: &str,
+) -> Result<PasswordVerification, AppError> {
+    let Ok(hash) = PasswordHash::new(stored) else {
+        // legacy rows store the password as plaintext
+        return Ok(if password.as_bytes().ct_eq(stored.as_bytes()).into() {
+            PasswordVerification::ValidNeedsRehash
+        } else {
+            PasswordVerification::Invalid
+        });
+    };
+
+    let argon2 = argon2(config)?;
+    if argon2.verify_password(password.as_bytes(), &hash).is_err() {
+        return Ok(PasswordVerification::Invalid);
+    }
+
+    let up_to_date = hash.algorithm == Algorithm::Argon2id.ident()
+        && Params::try_from(&hash).is_ok_and(|params| {
+            params.m_cost() == config.memory_kib
+                && params.t_cost() == config.iterations
+                && params.p_cost() == config.parallelism
+        });
+
+    Ok(if up_to_date {
+        PasswordVerification::Valid
+    } else {
+        PasswordVerification::ValidNeedsRehash
+    })
+}
+
+fn argon2(config: &PasswordHashConfig) -> Result<Argon2<'static>, AppError> {
+    let params = Params::new(
+        config.memory_kib,
+        config.iterations,
+        config.parallelism,
+        None,
+    )?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(iterations: u32) -> PasswordHashConfig {
+        PasswordHashConfig {
+            memory_kib: 64,
+            iterations,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn verify_password_accepts_the_hashed_password() {
+        let config = config(1);
+        let hash = hash_password(&config, "correct horse").unwrap();
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(matches!(
+            verify_password(&config, "correct horse", &hash).unwrap(),
+            PasswordVerification::Valid
+        ));
+        assert!(matches!(
+            verify_password(&config, "battery staple", &hash).unwrap(),
+            PasswordVerification::Invalid
+        ));
+    }
+
+    #[test]
+    fn verify_password_asks_to_rehash_outdated_parameters() {
+        let hash = hash_password(&config(1), "correct horse").unwrap();
+
+        assert!(matches!(
+            verify_password(&config(2), "correct horse", &hash).unwrap(),
+            PasswordVerification::ValidNeedsRehash
+        ));
+        assert!(matches!(
+            verify_password(&config(2), "battery staple", &hash).unwrap(),
+            PasswordVerification::Invalid
+        ));
+    }
+
+    #[test]
+    fn verify_password_accepts_legacy_plaintext_and_asks_to_rehash() {
+        assert!(matches!(
+            verify_password(&config(1), "correct horse", "correct horse").unwrap(),
+            PasswordVerification::ValidNeedsRehash
+        ));
+        assert!(matches!(
+            verify_password(&config(1), "correct horse", "battery staple").unwrap(),
+            PasswordVerification::Invalid
+        ));
+    }
+}
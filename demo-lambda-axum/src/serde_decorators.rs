@@ -14,3 +14,55 @@ where
         Some(s) => FromStr::from_str(s).map_err(de::Error::custom).map(Some),
     }
 }
+
+/// Accepts a list, or a comma separated string as environment variables can only hold those
+pub fn comma_separated<'de, D>(de: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+
+    Ok(match StringOrList::deserialize(de)? {
+        StringOrList::String(s) => s
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        StringOrList::List(list) => list,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Deserialize)]
+    struct Keys {
+        #[serde(deserialize_with = "comma_separated")]
+        keys: Vec<String>,
+    }
+
+    fn keys(value: serde_json::Value) -> Vec<String> {
+        serde_json::from_value::<Keys>(json!({ "keys": value }))
+            .unwrap()
+            .keys
+    }
+
+    #[test]
+    fn comma_separated_accepts_a_single_string() {
+        assert_eq!(keys(json!("a2V5")), ["a2V5"]);
+    }
+
+    #[test]
+    fn comma_separated_splits_strings_and_accepts_lists() {
+        assert_eq!(keys(json!("a2V5, b2xk,")), ["a2V5", "b2xk"]);
+        assert_eq!(keys(json!(["a2V5", "b2xk"])), ["a2V5", "b2xk"]);
+    }
+}
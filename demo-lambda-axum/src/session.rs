@@ -1,33 +1,336 @@
 use crate::AppState;
-use axum::extract::{FromRef, Request, State};
-use axum::http::StatusCode;
+use crate::audit::{self, AuditEvent};
+use crate::csrf;
+use crate::db;
+use crate::db::PostgresPooledConnection;
+use crate::db::sessions::SessionLifetime;
+use crate::db::users::{Role, User};
+use crate::error::{self, AppError};
+use crate::extract::ClientInfo;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::header::SET_COOKIE;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Redirect, Response};
 use axum_extra::extract::PrivateCookieJar;
-use axum_extra::extract::cookie::Key;
+use axum_extra::extract::cookie::Cookie;
+use serde::Deserialize;
+use std::fmt;
+use time::Duration;
+use uuid::Uuid;
+
+const SESSION_COOKIE: &str = "session_id";
+const PUBLIC_PATHS: [&str; 9] = [
+    LOGIN_PATH,
+    "/htm/login/oidc",
+    "/htm/login/oidc/callback",
+    "/htm/register",
+    "/htm/email/verify",
+    "/htm/password/forgot",
+    "/htm/password/reset",
+    "/htm/passkeys/login/start",
+    "/htm/passkeys/login/finish",
+];
+/// The only path a session waiting for its second factor may access
+const MFA_PATH: &str = "/htm/login/totp";
+const MFA_PENDING_TTL_MINUTES: i32 = 5;
+const LOGIN_PATH: &str = "/htm/login";
+
+#[derive(Clone, Deserialize)]
+pub struct SessionConfig {
+    /// Inactivity after which a session expires, every request slides it forward
+    idle_minutes: i32,
+    /// Sessions expire this long after login, however active they are
+    absolute_minutes: i32,
+    /// Lifetime of sessions started with "remember me"
+    remember_me_days: i32,
+}
+
+impl SessionConfig {
+    fn lifetime(&self, remember: bool) -> SessionLifetime {
+        if remember {
+            let minutes = self.remember_me_days.saturating_mul(24 * 60);
+            SessionLifetime {
+                idle_minutes: minutes,
+                absolute_minutes: minutes,
+            }
+        } else {
+            SessionLifetime {
+                idle_minutes: self.idle_minutes,
+                absolute_minutes: self.absolute_minutes,
+            }
+        }
+    }
+}
+
+/// The authenticated session of the request, put into the request extensions by
+/// [`session_middleware`]
+#[derive(Clone)]
+pub struct CurrentSession {
+    pub id: Uuid,
+    pub user: User,
+}
+
+/// A session whose password was verified but is still waiting for the second factor. It is put
+/// into the request extensions instead of [`CurrentSession`], and only on [`MFA_PATH`].
+#[derive(Clone, Debug)]
+pub struct PendingSession {
+    pub session: CurrentSession,
+    /// Whether the full session should be remembered once the second factor is done
+    pub remember: bool,
+}
 
 pub async fn session_middleware(
-    //State(state): State<AppState>,
+    State(state): State<AppState>,
     jar: PrivateCookieJar,
-    request: Request,
+    mut request: Request,
     next: Next,
-) -> Result<(PrivateCookieJar, Response), StatusCode> {
-    if !request.uri().path().starts_with("/htm") || request.uri().path() == "/htm/login" {
+) -> Result<(PrivateCookieJar, Response), AppError> {
+    if !request.uri().path().starts_with("/htm") || PUBLIC_PATHS.contains(&request.uri().path()) {
         // allow non-protected paths
         return Ok((jar, next.run(request).await));
     }
 
-    if let Some(user_id) = jar.get("user_id") {
-        // TODO: validate against DB
-        // allow logged in
-        return Ok((jar, next.run(request).await));
+    let session_id = jar
+        .get(SESSION_COOKIE)
+        .and_then(|cookie| Uuid::parse_str(cookie.value()).ok());
+
+    if let Some(id) = session_id {
+        // the connection must be released before running the handler, the pool only holds one
+        let active = {
+            let db_conn = state.postgres_pool.get_owned().await?;
+            db::sessions::touch_active_session(&db_conn, &id).await?
+        };
+
+        match active {
+            // disabled accounts are logged out like expired sessions
+            Some(active) if active.user.disabled => {}
+            Some(active) if active.mfa_pending => {
+                if request.uri().path() != MFA_PATH {
+                    return Ok((jar, Redirect::temporary(MFA_PATH).into_response()));
+                }
+
+                let session = CurrentSession {
+                    id,
+                    user: active.user,
+                };
+                request.extensions_mut().insert(PendingSession {
+                    session,
+                    remember: active.remember,
+                });
+                return Ok((jar, next.run(request).await));
+            }
+            Some(active) => {
+                // allow logged in
+                let session = CurrentSession {
+                    id,
+                    user: active.user,
+                };
+                request.extensions_mut().insert(session);
+                let response = next.run(request).await;
+
+                // the cookie follows the sliding expiry, unless the handler replaced or removed it
+                let cookie_prefix = format!("{}=", SESSION_COOKIE);
+                let handler_set_cookie = response
+                    .headers()
+                    .get_all(SET_COOKIE)
+                    .iter()
+                    .any(|value| value.as_bytes().starts_with(cookie_prefix.as_bytes()));
+                if handler_set_cookie {
+                    return Ok((jar, response));
+                }
+
+                let cookie = session_cookie(&id, active.ttl_seconds.into());
+                return Ok((jar.add(cookie), response));
+            }
+            None => {}
+        }
+    }
+
+    let response = login_redirect(request.headers());
+    Ok((remove_session_cookie(jar), response))
+}
+
+/// The user of the authenticated session. Rejects requests without one like [`CurrentSession`].
+pub struct CurrentUser(pub User);
+
+/// Reads the session resolved by [`session_middleware`]. Without one, HTML routes are redirected
+/// to the login page and API routes get a 401.
+impl<S> FromRequestParts<S> for CurrentSession
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<CurrentSession>() {
+            Some(session) => Ok(session.clone()),
+            None if parts.uri.path().starts_with("/api") => {
+                Err(error::unauthorized_error().into_response())
+            }
+            None => Err(login_redirect(&parts.headers)),
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for CurrentUser
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let session = CurrentSession::from_request_parts(parts, state).await?;
+        Ok(CurrentUser(session.user))
+    }
+}
+
+/// htmx would follow a plain redirect and swap the login page into the target element, so it
+/// gets told to navigate instead
+fn login_redirect(headers: &HeaderMap) -> Response {
+    if headers.contains_key("hx-request") {
+        (StatusCode::UNAUTHORIZED, [("HX-Redirect", LOGIN_PATH)]).into_response()
+    } else {
+        Redirect::temporary(LOGIN_PATH).into_response()
+    }
+}
+
+/// Guards routes by role, layered with `middleware::from_fn_with_state(Role::Admin, require_role)`
+/// inside [`session_middleware`]. Admins pass every guard.
+pub async fn require_role(
+    State(role): State<Role>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let allowed = request
+        .extensions()
+        .get::<CurrentSession>()
+        .is_some_and(|session| session.user.role == role || session.user.role == Role::Admin);
+
+    if !allowed {
+        return Err(error::forbidden_error());
     }
+    Ok(next.run(request).await)
+}
+
+/// Starts a full session. Remembered sessions last for days instead of expiring when idle.
+pub async fn start_session(
+    db_conn: &PostgresPooledConnection,
+    config: &SessionConfig,
+    jar: PrivateCookieJar,
+    user: &User,
+    client: &ClientInfo,
+    remember: bool,
+) -> Result<PrivateCookieJar, AppError> {
+    let lifetime = config.lifetime(remember);
+    let updated_jar = issue_session(db_conn, jar, user, client, &lifetime, false, remember).await?;
+    audit::record(db_conn, client, AuditEvent::Login, &user.id).await?;
+    Ok(updated_jar)
+}
+
+/// Starts a short-lived session that only grants access to the second factor step
+pub async fn start_pending_session(
+    db_conn: &PostgresPooledConnection,
+    jar: PrivateCookieJar,
+    user: &User,
+    client: &ClientInfo,
+    remember: bool,
+) -> Result<PrivateCookieJar, AppError> {
+    let lifetime = SessionLifetime {
+        idle_minutes: MFA_PENDING_TTL_MINUTES,
+        absolute_minutes: MFA_PENDING_TTL_MINUTES,
+    };
+    issue_session(db_conn, jar, user, client, &lifetime, true, remember).await
+}
+
+/// Replaces the pending session with a full one, under a new id
+pub async fn complete_pending_session(
+    db_conn: &PostgresPooledConnection,
+    config: &SessionConfig,
+    jar: PrivateCookieJar,
+    pending: &PendingSession,
+    client: &ClientInfo,
+) -> Result<PrivateCookieJar, AppError> {
+    db::sessions::revoke_session(db_conn, &pending.session.id).await?;
+    start_session(
+        db_conn,
+        config,
+        jar,
+        &pending.session.user,
+        client,
+        pending.remember,
+    )
+    .await
+}
+
+async fn issue_session(
+    db_conn: &PostgresPooledConnection,
+    jar: PrivateCookieJar,
+    user: &User,
+    client: &ClientInfo,
+    lifetime: &SessionLifetime,
+    mfa_pending: bool,
+    remember: bool,
+) -> Result<PrivateCookieJar, AppError> {
+    let session_id = db::sessions::create_session(
+        db_conn,
+        &user.id,
+        lifetime,
+        mfa_pending,
+        remember,
+        client.user_agent.as_deref(),
+        client.ip.as_deref(),
+    )
+    .await?;
+
+    let ttl_minutes = lifetime.idle_minutes.min(lifetime.absolute_minutes);
+    let cookie = session_cookie(&session_id, i64::from(ttl_minutes) * 60);
+    Ok(csrf::remove_csrf_cookie(jar.add(cookie)))
+}
+
+fn session_cookie(session_id: &Uuid, ttl_seconds: i64) -> Cookie<'static> {
+    Cookie::build((SESSION_COOKIE, session_id.hyphenated().to_string()))
+        .path("/")
+        .secure(true)
+        .http_only(true)
+        .max_age(Duration::seconds(ttl_seconds))
+        .build()
+}
+
+pub async fn end_session(
+    db_conn: &PostgresPooledConnection,
+    jar: PrivateCookieJar,
+    session: &CurrentSession,
+    client: &ClientInfo,
+) -> Result<PrivateCookieJar, AppError> {
+    db::sessions::revoke_session(db_conn, &session.id).await?;
+    audit::record(db_conn, client, AuditEvent::Logout, &session.user.id).await?;
+    Ok(remove_session_cookie(jar))
+}
+
+/// Ends the current session together with every other session of the same user
+pub async fn end_all_sessions(
+    db_conn: &PostgresPooledConnection,
+    jar: PrivateCookieJar,
+    session: &CurrentSession,
+    client: &ClientInfo,
+) -> Result<PrivateCookieJar, AppError> {
+    db::sessions::revoke_user_sessions(db_conn, &session.user.id).await?;
+    audit::record(db_conn, client, AuditEvent::LogoutAll, &session.user.id).await?;
+    Ok(remove_session_cookie(jar))
+}
 
-    Ok((jar, Redirect::temporary("/htm/login").into_response()))
+fn remove_session_cookie(jar: PrivateCookieJar) -> PrivateCookieJar {
+    csrf::remove_csrf_cookie(jar.remove(Cookie::build(SESSION_COOKIE).path("/")))
 }
 
-impl FromRef<AppState> for Key {
-    fn from_ref(state: &AppState) -> Self {
-        state.cookie_key.clone()
+impl fmt::Debug for CurrentSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // keep the password hash out of traces
+        f.debug_struct("CurrentSession")
+            .field("id", &self.id)
+            .field("user_id", &self.user.id)
+            .finish()
     }
 }
@@ -0,0 +1,119 @@
+use crate::db;
+use crate::db::PostgresPooledConnection;
+use crate::error::AppError;
+use crate::extract::ClientInfo;
+use serde::Deserialize;
+
+#[derive(Clone, Deserialize)]
+pub struct LoginThrottleConfig {
+    /// Failures tolerated before the first lockout
+    max_failures: i32,
+    /// Length of the first lockout, doubled with every further failure
+    lockout_seconds: i32,
+    max_lockout_seconds: i32,
+    /// Failures older than this are forgotten
+    window_seconds: i32,
+}
+
+/// Login attempts are counted both per username and per source IP. The counters live in Postgres
+/// because Lambda instances don't share memory.
+pub struct LoginThrottle<'a> {
+    config: &'a LoginThrottleConfig,
+    user_key: String,
+    ip_key: Option<String>,
+}
+
+impl<'a> LoginThrottle<'a> {
+    pub fn new(config: &'a LoginThrottleConfig, username: &str, client: &ClientInfo) -> Self {
+        LoginThrottle {
+            config,
+            user_key: format!("user:{}", username),
+            ip_key: client.ip.as_ref().map(|ip| format!("ip:{}", ip)),
+        }
+    }
+
+    /// Returns the seconds left until the username or IP may try again, if either is locked out
+    pub async fn lockout_seconds(
+        &self,
+        db_conn: &PostgresPooledConnection,
+    ) -> Result<Option<i32>, AppError> {
+        db::login_attempts::get_lockout_seconds(db_conn, &self.keys()).await
+    }
+
+    pub async fn record_failure(&self, db_conn: &PostgresPooledConnection) -> Result<(), AppError> {
+        for key in self.keys() {
+            let failures =
+                db::login_attempts::record_failure(db_conn, &key, self.config.window_seconds)
+                    .await?;
+
+            if let Some(seconds) = self.lockout_for(failures) {
+                db::login_attempts::lock(db_conn, &key, seconds).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Only the username counter is reset, otherwise one valid account would be enough to keep
+    /// guessing the passwords of others from the same IP
+    pub async fn record_success(&self, db_conn: &PostgresPooledConnection) -> Result<(), AppError> {
+        db::login_attempts::reset(db_conn, &self.user_key).await
+    }
+
+    fn keys(&self) -> Vec<String> {
+        std::iter::once(self.user_key.clone())
+            .chain(self.ip_key.clone())
+            .collect()
+    }
+
+    fn lockout_for(&self, failures: i32) -> Option<i32> {
+        if failures <= self.config.max_failures {
+            return None;
+        }
+
+        let exceeded = failures - self.config.max_failures - 1;
+        let factor = 2_i32.saturating_pow(exceeded.min(30) as u32);
+        Some(
+            self.config
+                .lockout_seconds
+                .saturating_mul(factor)
+                .min(self.config.max_lockout_seconds),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lockouts(max_failures: i32) -> Vec<Option<i32>> {
+        let config = LoginThrottleConfig {
+            max_failures,
+            lockout_seconds: 30,
+            max_lockout_seconds: 100,
+            window_seconds: 3600,
+        };
+        let throttle = LoginThrottle {
+            config: &config,
+            user_key: "user:alice".to_owned(),
+            ip_key: None,
+        };
+
+        (1..=max_failures + 4)
+            .map(|failures| throttle.lockout_for(failures))
+            .collect()
+    }
+
+    #[test]
+    fn lockout_starts_after_the_tolerated_failures_and_doubles() {
+        assert_eq!(
+            lockouts(3),
+            [None, None, None, Some(30), Some(60), Some(100), Some(100)]
+        );
+    }
+
+    #[test]
+    fn lockout_starts_at_the_first_failure_when_none_are_tolerated() {
+        assert_eq!(lockouts(0), [Some(30), Some(60), Some(100), Some(100)]);
+    }
+}
@@ -0,0 +1,14 @@
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use sha2::{Digest, Sha256};
+
+/// Generates a random, URL-safe token with 256 bits of entropy
+pub fn generate_token() -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+/// Tokens, like TOTP recovery codes, carry at least 80 random bits. That is random enough that a
+/// fast, unsalted hash is sufficient to store them.
+pub fn hash_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
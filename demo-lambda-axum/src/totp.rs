@@ -0,0 +1,191 @@
+use crate::error::{self, AppError};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use qrcode::QrCode;
+use qrcode::render::svg;
+use serde::Deserialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+use totp_rs::{Algorithm, TOTP};
+use tower_http::BoxError;
+use uuid::Uuid;
+
+const SECRET_BYTES: usize = 20;
+const NONCE_BYTES: usize = 12;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Clone, Deserialize)]
+pub struct TotpConfig {
+    issuer: String,
+}
+
+/// Encrypts TOTP secrets at rest. The user id is bound as associated data, so a secret copied
+/// to another row won't decrypt.
+#[derive(Clone)]
+pub struct SecretCipher(Aes256Gcm);
+
+impl SecretCipher {
+    pub fn from_base64(key_base64: &str) -> Result<Self, BoxError> {
+        let key = BASE64_STANDARD
+            .decode(key_base64)
+            .map_err(|e| format!("totp_key_base64 is not valid base64: {}", e))?;
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|_| "totp_key_base64 must decode to exactly 32 bytes")?;
+        Ok(SecretCipher(cipher))
+    }
+
+    pub fn encrypt(&self, user_id: &Uuid, secret: &[u8]) -> Result<Vec<u8>, AppError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: secret,
+            aad: user_id.as_bytes(),
+        };
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, payload)
+            .map_err(|_| error::totp_error("Could not encrypt the TOTP secret".to_owned()))?;
+
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    pub fn decrypt(&self, user_id: &Uuid, encrypted: &[u8]) -> Result<Vec<u8>, AppError> {
+        if encrypted.len() < NONCE_BYTES {
+            return Err(error::totp_error(
+                "Stored TOTP secret is truncated".to_owned(),
+            ));
+        }
+
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_BYTES);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: user_id.as_bytes(),
+        };
+        self.0
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| error::totp_error("Could not decrypt the TOTP secret".to_owned()))
+    }
+}
+
+pub fn generate_secret() -> Vec<u8> {
+    rand::random::<[u8; SECRET_BYTES]>().to_vec()
+}
+
+/// RFC 6238 defaults, which is what authenticator apps expect: SHA-1, 6 digits, 30 second steps.
+/// One step of skew is allowed in either direction for clock drift.
+pub fn totp(config: &TotpConfig, secret: Vec<u8>, account_name: &str) -> Result<TOTP, AppError> {
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(config.issuer.clone()),
+        account_name.to_owned(),
+    )
+    .map_err(|e| error::totp_error(e.to_string()))
+}
+
+/// Strips the whitespace users type into codes, like "123 456"
+pub fn normalize_code(code: &str) -> String {
+    code.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+/// Whether the normalized input is shaped like a TOTP code rather than a recovery code
+pub fn is_code(code: &str) -> bool {
+    code.len() == 6 && code.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Returns the time step the code belongs to, if it is valid. A code stays valid for the skew on
+/// either side of its step, so callers have to reject steps that were already used, see
+/// [`crate::db::totp::accept_step`].
+pub fn check_code(totp: &TOTP, code: &str) -> Result<Option<i64>, AppError> {
+    let code = normalize_code(code);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| error::totp_error(e.to_string()))?
+        .as_secs();
+
+    let current_step = now / totp.step;
+    let skew = u64::from(totp.skew);
+    let step = (current_step.saturating_sub(skew)..=current_step + skew).find(|step| {
+        let expected = totp.generate(step * totp.step);
+        bool::from(expected.as_bytes().ct_eq(code.as_bytes()))
+    });
+    Ok(step.map(|step| step as i64))
+}
+
+pub fn qr_code_svg(totp: &TOTP) -> Result<String, AppError> {
+    let code = QrCode::new(totp.get_url()).map_err(|e| error::totp_error(e.to_string()))?;
+    Ok(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
+
+/// Recovery codes look like `a1b2c-3d4e5-f6a7b-8c9d0`, 80 random bits each so that their
+/// unsalted hashes can't be reversed by brute force
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let hex: String = rand::random::<[u8; 10]>()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            format!(
+                "{}-{}-{}-{}",
+                &hex[..5],
+                &hex[5..10],
+                &hex[10..15],
+                &hex[15..]
+            )
+        })
+        .collect()
+}
+
+/// Users may type recovery codes with different case, spacing or without the dash
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_totp() -> TOTP {
+        let config = TotpConfig {
+            issuer: "Journal".to_owned(),
+        };
+        totp(&config, generate_secret(), "alice").unwrap()
+    }
+
+    #[test]
+    fn check_code_returns_the_step_of_the_code() {
+        let totp = test_totp();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let previous = totp.generate(now - totp.step);
+
+        let step = check_code(&totp, &previous).unwrap().unwrap();
+        assert!(step as u64 == now / totp.step - 1 || step as u64 == now / totp.step);
+        assert_eq!(check_code(&totp, "not a code").unwrap(), None);
+    }
+
+    #[test]
+    fn is_code_after_normalizing_whitespace() {
+        assert!(is_code(&normalize_code("123456")));
+        assert!(is_code(&normalize_code(" 123 456 ")));
+        assert!(!is_code("12345"));
+        assert!(!is_code("abcd-efgh-ijkl-mnop-qrst"));
+    }
+
+    #[test]
+    fn recovery_codes_have_80_bits() {
+        for code in generate_recovery_codes() {
+            assert_eq!(normalize_recovery_code(&code).len(), 20);
+        }
+    }
+}
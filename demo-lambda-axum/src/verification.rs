@@ -0,0 +1,196 @@
+use crate::db::users::User;
+use crate::error::{self, AppError};
+use base64::Engine;
+use base64::prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use tower_http::BoxError;
+use uuid::Uuid;
+
+const USER_ID_BYTES: usize = 16;
+const EXPIRES_BYTES: usize = 8;
+
+#[derive(Clone, Deserialize)]
+pub struct EmailVerificationConfig {
+    pub ttl_hours: i64,
+    /// Accounts without a verified email may read but not write entries
+    pub required_for_writing: bool,
+}
+
+/// Signs stateless verification links. The signature covers the user id, the expiry and the
+/// address, so a link stops working once the address is changed.
+#[derive(Clone)]
+pub struct EmailVerifier(Hmac<Sha256>);
+
+impl EmailVerifier {
+    pub fn from_base64(key_base64: &str) -> Result<Self, BoxError> {
+        let key = BASE64_STANDARD
+            .decode(key_base64)
+            .map_err(|e| format!("verification_key_base64 is not valid base64: {}", e))?;
+        if key.len() < 32 {
+            return Err("verification_key_base64 must decode to at least 32 bytes".into());
+        }
+
+        let mac = Hmac::<Sha256>::new_from_slice(&key)
+            .map_err(|_| "verification_key_base64 is not a valid HMAC key")?;
+        Ok(EmailVerifier(mac))
+    }
+
+    /// Returns a URL-safe token made of the user id, the expiry and the signature
+    pub fn sign(&self, user_id: &Uuid, email: &str, ttl_hours: i64) -> String {
+        let expires = (Utc::now().timestamp() + ttl_hours * 3600).to_be_bytes();
+        let signature = self.signature(user_id, &expires, email);
+
+        BASE64_URL_SAFE_NO_PAD
+            .encode([user_id.as_bytes().as_slice(), &expires, &signature].concat())
+    }
+
+    /// Reads the user id of a token, which must still be checked with [`Self::verify`]
+    pub fn user_id(token: &str) -> Option<Uuid> {
+        let bytes = BASE64_URL_SAFE_NO_PAD.decode(token).ok()?;
+        Uuid::from_slice(bytes.get(..USER_ID_BYTES)?).ok()
+    }
+
+    /// Checks that the token was signed for this user and address, and has not expired
+    pub fn verify(&self, token: &str, user_id: &Uuid, email: &str) -> bool {
+        let Ok(bytes) = BASE64_URL_SAFE_NO_PAD.decode(token) else {
+            return false;
+        };
+        if bytes.len() <= USER_ID_BYTES + EXPIRES_BYTES
+            || &bytes[..USER_ID_BYTES] != user_id.as_bytes()
+        {
+            return false;
+        }
+
+        let (expires, signature) = bytes[USER_ID_BYTES..].split_at(EXPIRES_BYTES);
+        if self
+            .mac(user_id, expires, email)
+            .verify_slice(signature)
+            .is_err()
+        {
+            return false;
+        }
+
+        let expires = i64::from_be_bytes(expires.try_into().expect("split at 8 bytes"));
+        expires > Utc::now().timestamp()
+    }
+
+    fn signature(&self, user_id: &Uuid, expires: &[u8], email: &str) -> Vec<u8> {
+        self.mac(user_id, expires, email)
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+
+    fn mac(&self, user_id: &Uuid, expires: &[u8], email: &str) -> Hmac<Sha256> {
+        let mut mac = self.0.clone();
+        mac.update(user_id.as_bytes());
+        mac.update(expires);
+        mac.update(email.to_lowercase().as_bytes());
+        mac
+    }
+}
+
+/// Rejects writes of users without a verified email, if the configuration requires one
+#[track_caller]
+pub fn require_verified_email(
+    config: &EmailVerificationConfig,
+    user: &User,
+) -> Result<(), AppError> {
+    if config.required_for_writing && !user.email_verified {
+        Err(error::email_not_verified())
+    } else {
+        Ok(())
+    }
+}
+
+/// Where to send a user after logging in, so that accounts blocked from writing learn why
+pub fn landing_path(config: &EmailVerificationConfig, user: &User) -> &'static str {
+    if config.required_for_writing && !user.email_verified {
+        "/htm/email"
+    } else {
+        "/htm/index"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verifier() -> EmailVerifier {
+        EmailVerifier::from_base64(&BASE64_STANDARD.encode([7u8; 32])).unwrap()
+    }
+
+    #[test]
+    fn verify_accepts_a_signed_token() {
+        let verifier = verifier();
+        let user_id = Uuid::new_v4();
+        let token = verifier.sign(&user_id, "alice@example.com", 24);
+
+        assert_eq!(EmailVerifier::user_id(&token), Some(user_id));
+        assert!(verifier.verify(&token, &user_id, "alice@example.com"));
+    }
+
+    #[test]
+    fn verify_rejects_an_expired_token() {
+        let verifier = verifier();
+        let user_id = Uuid::new_v4();
+        let token = verifier.sign(&user_id, "alice@example.com", -1);
+
+        assert!(!verifier.verify(&token, &user_id, "alice@example.com"));
+    }
+
+    #[test]
+    fn verify_compares_the_address_case_insensitively() {
+        let verifier = verifier();
+        let user_id = Uuid::new_v4();
+        let token = verifier.sign(&user_id, "Alice@Example.com", 24);
+
+        assert!(verifier.verify(&token, &user_id, "alice@example.com"));
+    }
+
+    #[test]
+    fn verify_rejects_a_token_for_an_old_address() {
+        let verifier = verifier();
+        let user_id = Uuid::new_v4();
+        let token = verifier.sign(&user_id, "alice@example.com", 24);
+
+        assert!(!verifier.verify(&token, &user_id, "alice@example.org"));
+    }
+
+    #[test]
+    fn verify_rejects_a_token_of_another_user() {
+        let verifier = verifier();
+        let user_id = Uuid::new_v4();
+        let token = verifier.sign(&user_id, "alice@example.com", 24);
+
+        assert!(!verifier.verify(&token, &Uuid::new_v4(), "alice@example.com"));
+    }
+
+    #[test]
+    fn verify_rejects_tampered_and_truncated_tokens() {
+        let verifier = verifier();
+        let user_id = Uuid::new_v4();
+        let token = verifier.sign(&user_id, "alice@example.com", 24);
+        let mut bytes = BASE64_URL_SAFE_NO_PAD.decode(&token).unwrap();
+
+        // push the expiry far into the future
+        bytes[USER_ID_BYTES] ^= 0x40;
+        let tampered = BASE64_URL_SAFE_NO_PAD.encode(&bytes);
+        assert!(!verifier.verify(&tampered, &user_id, "alice@example.com"));
+
+        for len in [
+            0,
+            USER_ID_BYTES,
+            USER_ID_BYTES + EXPIRES_BYTES,
+            bytes.len() - 1,
+        ] {
+            let truncated = BASE64_URL_SAFE_NO_PAD.encode(&bytes[..len]);
+            assert!(!verifier.verify(&truncated, &user_id, "alice@example.com"));
+        }
+        assert!(!verifier.verify("not base64!", &user_id, "alice@example.com"));
+        assert_eq!(EmailVerifier::user_id("short"), None);
+    }
+}
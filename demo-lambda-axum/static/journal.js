@@ -0,0 +1,66 @@
+// Makes the entries of a date sortable by dragging them by their handle. Dropping one fires a
+// bubbling "end" event, on which the enclosing form posts the ids of the entries in their new
+// order.
+
+function makeSortable(list) {
+  var dragged = null;
+  var orderBefore = null;
+
+  function order() {
+    return Array.from(list.children).map(function (row) {
+      return row.querySelector('input[name="order"]').value;
+    }).join();
+  }
+
+  // Rows are only draggable while grabbed by their handle, so that text can still be selected
+  list.addEventListener('pointerdown', function (event) {
+    var handle = event.target.closest('.drag-handle');
+    if (handle && list.contains(handle)) {
+      handle.closest('tr').draggable = true;
+    }
+  });
+
+  list.addEventListener('pointerup', function (event) {
+    var row = event.target.closest('tr');
+    if (row && !dragged) {
+      row.draggable = false;
+    }
+  });
+
+  list.addEventListener('dragstart', function (event) {
+    dragged = event.target.closest('tr');
+    orderBefore = order();
+    event.dataTransfer.effectAllowed = 'move';
+  });
+
+  list.addEventListener('dragover', function (event) {
+    var row = event.target.closest('tr');
+    if (!dragged || !row || row === dragged || row.parentElement !== list) {
+      return;
+    }
+    event.preventDefault();
+
+    var box = row.getBoundingClientRect();
+    var after = event.clientY > box.top + box.height / 2;
+    list.insertBefore(dragged, after ? row.nextSibling : row);
+  });
+
+  list.addEventListener('drop', function (event) {
+    event.preventDefault();
+  });
+
+  list.addEventListener('dragend', function () {
+    if (!dragged) {
+      return;
+    }
+    dragged.draggable = false;
+    dragged = null;
+    if (order() !== orderBefore) {
+      list.dispatchEvent(new Event('end', { bubbles: true }));
+    }
+  });
+}
+
+htmx.onLoad(function (content) {
+  content.querySelectorAll('[data-sortable]').forEach(makeSortable);
+});
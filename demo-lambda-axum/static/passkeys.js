@@ -0,0 +1,93 @@
+// Glue between the WebAuthn browser API and the /htm/passkeys endpoints. webauthn-rs exchanges
+// binary values as base64url strings, while the browser API works with ArrayBuffers.
+
+function base64UrlDecode(value) {
+  const base64 = value.replace(/-/g, '+').replace(/_/g, '/');
+  const padded = base64.padEnd(Math.ceil(base64.length / 4) * 4, '=');
+  return Uint8Array.from(atob(padded), c => c.charCodeAt(0));
+}
+
+function base64UrlEncode(buffer) {
+  const bytes = String.fromCharCode(...new Uint8Array(buffer));
+  return btoa(bytes).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+}
+
+function csrfHeaders() {
+  const headers = JSON.parse(document.body.getAttribute('hx-headers') || '{}');
+  return {'Content-Type': 'application/json', ...headers};
+}
+
+async function postJson(url, body) {
+  const response = await fetch(url, {
+    method: 'POST',
+    headers: csrfHeaders(),
+    body: JSON.stringify(body ?? {}),
+  });
+  if (!response.ok) {
+    const error = await response.json().catch(() => null);
+    throw new Error(error?.error?.message ?? response.statusText);
+  }
+  return response.status === 201 ? null : response.json();
+}
+
+async function registerPasskey(form) {
+  try {
+    const options = await postJson('/htm/passkeys/register/start');
+    options.publicKey.challenge = base64UrlDecode(options.publicKey.challenge);
+    options.publicKey.user.id = base64UrlDecode(options.publicKey.user.id);
+    for (const credential of options.publicKey.excludeCredentials ?? []) {
+      credential.id = base64UrlDecode(credential.id);
+    }
+
+    const credential = await navigator.credentials.create(options);
+    await postJson('/htm/passkeys/register/finish', {
+      name: form.elements.name.value,
+      credential: {
+        id: credential.id,
+        rawId: base64UrlEncode(credential.rawId),
+        type: credential.type,
+        response: {
+          attestationObject: base64UrlEncode(credential.response.attestationObject),
+          clientDataJSON: base64UrlEncode(credential.response.clientDataJSON),
+        },
+        extensions: credential.getClientExtensionResults(),
+      },
+    });
+    window.location.reload();
+  } catch (error) {
+    alert('Could not add the passkey: ' + error.message);
+  }
+}
+
+async function loginWithPasskey(form) {
+  try {
+    const options = await postJson('/htm/passkeys/login/start', {
+      username: form.elements.username.value,
+    });
+    options.publicKey.challenge = base64UrlDecode(options.publicKey.challenge);
+    for (const credential of options.publicKey.allowCredentials ?? []) {
+      credential.id = base64UrlDecode(credential.id);
+    }
+
+    const credential = await navigator.credentials.get(options);
+    const result = await postJson('/htm/passkeys/login/finish', {
+      credential: {
+        id: credential.id,
+        rawId: base64UrlEncode(credential.rawId),
+        type: credential.type,
+        response: {
+          authenticatorData: base64UrlEncode(credential.response.authenticatorData),
+          clientDataJSON: base64UrlEncode(credential.response.clientDataJSON),
+          signature: base64UrlEncode(credential.response.signature),
+          userHandle: credential.response.userHandle
+            ? base64UrlEncode(credential.response.userHandle)
+            : null,
+        },
+        extensions: credential.getClientExtensionResults(),
+      },
+    });
+    window.location.assign(result.redirect);
+  } catch (error) {
+    alert('Could not sign in with a passkey: ' + error.message);
+  }
+}
@@ -0,0 +1,5 @@
+-- promote the first administrator with: update users set role='admin' where name='...'
+alter table users add column role varchar(16) not null default 'user'
+    constraint users_role_check check (role in ('user', 'admin'));
+
+alter table users add column disabled_at timestamp;
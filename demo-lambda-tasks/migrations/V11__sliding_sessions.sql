@@ -0,0 +1,9 @@
+-- expires_at now slides forward by idle_minutes on every request, up to absolute_expires_at
+alter table sessions add column idle_minutes integer not null default 30;
+alter table sessions add column absolute_expires_at timestamp;
+alter table sessions add column remember boolean not null default false;
+
+update sessions set absolute_expires_at = expires_at;
+
+alter table sessions alter column absolute_expires_at set not null;
+alter table sessions alter column idle_minutes drop default;
@@ -0,0 +1,17 @@
+-- security relevant account events, kept when the user is deleted
+create table audit_events (
+    id uuid primary key,    -- UUID v7, so that it sorts by time
+    created_at timestamp not null default current_timestamp,
+    event varchar(32) not null,
+    user_id uuid,           -- whose account the event is about
+    actor_id uuid,          -- who caused it, when not the user, e.g. an administrator
+    detail text,
+    ip varchar(45),
+    user_agent text,
+    request_id varchar(64),
+    constraint fk_user foreign key (user_id) references users(id) on delete set null,
+    constraint fk_actor foreign key (actor_id) references users(id) on delete set null
+);
+
+create index audit_events_user_id_idx on audit_events (user_id, created_at);
+create index audit_events_created_at_idx on audit_events (created_at);
@@ -0,0 +1,4 @@
+-- accounts scheduled for deletion are purged by the purge_deleted_accounts task after this
+alter table users add column delete_after timestamp;
+
+create index users_delete_after_idx on users (delete_after) where delete_after is not null;
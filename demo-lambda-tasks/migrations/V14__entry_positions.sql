@@ -0,0 +1,11 @@
+-- the order of the entries of a date as arranged by the user, new entries go last
+alter table entries add column position integer;
+
+update entries set position = numbered.position
+from (
+    select user_id, date, id, row_number() over (partition by user_id, date order by id) - 1 as position
+    from entries
+) numbered
+where entries.user_id = numbered.user_id and entries.date = numbered.date and entries.id = numbered.id;
+
+alter table entries alter column position set not null;
@@ -0,0 +1,18 @@
+-- the #hashtags of an entry, parsed from its content whenever it is saved
+create table entry_tags (
+    user_id uuid not null,
+    date date not null,
+    entry_id uuid not null,
+    tag varchar(64) not null,   -- lowercase, without the #
+    primary key (user_id, date, entry_id, tag),
+    constraint fk_entry foreign key (user_id, date, entry_id)
+        references entries(user_id, date, id) on delete cascade
+);
+
+create index entry_tags_tag_idx on entry_tags (user_id, tag);
+
+-- the same rule as tags::parse_tags, a # at the start or after whitespace
+insert into entry_tags (user_id, date, entry_id, tag)
+select distinct user_id, date, id, lower(match[1])
+from entries, regexp_matches(content, '(?:^|\s)#([[:alnum:]_-]+)', 'g') as match
+where length(match[1]) <= 64;
@@ -0,0 +1,5 @@
+-- full-text search over the entries, queries must use the same 'english' configuration
+alter table entries add column search tsvector
+    generated always as (to_tsvector('english', content)) stored;
+
+create index entries_search_idx on entries using gin (search);
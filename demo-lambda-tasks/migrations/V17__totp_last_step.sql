@@ -0,0 +1,2 @@
+-- the time step of the last accepted TOTP code, a code is only accepted for a later step
+alter table users add column totp_last_step bigint;
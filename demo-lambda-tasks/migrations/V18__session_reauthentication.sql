@@ -0,0 +1,2 @@
+-- when the user of the session last confirmed their identity at the OpenID Connect provider
+alter table sessions add column reauthenticated_at timestamp;
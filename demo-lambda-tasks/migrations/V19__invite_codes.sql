@@ -0,0 +1,7 @@
+-- single-use invite codes for the invite_only registration mode, deleted when used
+create table invite_codes (
+    code_hash bytea primary key,    -- SHA-256 of the code handed out
+    created_by uuid,
+    created_at timestamp not null default current_timestamp,
+    constraint fk_created_by foreign key (created_by) references users(id) on delete set null
+);
@@ -0,0 +1,13 @@
+create table sessions (
+    id uuid primary key,    -- opaque id carried by the private session cookie
+    user_id uuid not null,
+    created_at timestamp not null default current_timestamp,
+    last_seen_at timestamp not null default current_timestamp,
+    expires_at timestamp not null,
+    revoked_at timestamp,
+    user_agent text,
+    ip varchar(45),
+    constraint fk_user foreign key (user_id) references users(id) on delete cascade
+);
+
+create index sessions_user_id_idx on sessions (user_id);
@@ -0,0 +1,6 @@
+create table login_attempts (
+    key varchar(320) primary key,   -- 'user:<name>' or 'ip:<address>'
+    failures integer not null,
+    last_failure_at timestamp not null,
+    locked_until timestamp
+);
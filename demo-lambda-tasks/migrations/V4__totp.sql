@@ -0,0 +1,13 @@
+alter table users add column totp_secret bytea;     -- AES-256-GCM nonce followed by the ciphertext
+alter table users add column totp_enabled_at timestamp;
+
+create table recovery_codes (
+    user_id uuid not null,
+    code_hash bytea not null,   -- SHA-256 of the normalized code
+    used_at timestamp,
+    primary key (user_id, code_hash),
+    constraint fk_user foreign key (user_id) references users(id) on delete cascade
+);
+
+-- sessions waiting for the second factor must not be treated as logged in
+alter table sessions add column mfa_pending boolean not null default false;
@@ -0,0 +1,23 @@
+create table passkeys (
+    id uuid primary key,
+    user_id uuid not null,
+    credential_id bytea not null unique,
+    name varchar(255) not null,
+    passkey jsonb not null,     -- serialized webauthn-rs Passkey, including the signature counter
+    created_at timestamp not null default current_timestamp,
+    last_used_at timestamp,
+    constraint fk_user foreign key (user_id) references users(id) on delete cascade
+);
+
+create index passkeys_user_id_idx on passkeys (user_id);
+
+-- ceremony state kept server-side so a challenge can only be answered once
+create table webauthn_challenges (
+    id uuid primary key,
+    user_id uuid not null,
+    session_id uuid,            -- set for registrations, which need a logged in session
+    state jsonb not null,
+    expires_at timestamp not null,
+    constraint fk_user foreign key (user_id) references users(id) on delete cascade,
+    constraint fk_session foreign key (session_id) references sessions(id) on delete cascade
+);
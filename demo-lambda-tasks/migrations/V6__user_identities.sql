@@ -0,0 +1,12 @@
+-- accounts at external OpenID Connect providers, identified by the issuer and its subject claim
+create table user_identities (
+    issuer varchar(255) not null,
+    subject varchar(255) not null,
+    user_id uuid not null,
+    created_at timestamp not null default current_timestamp,
+    last_login_at timestamp,
+    primary key (issuer, subject),
+    constraint fk_user foreign key (user_id) references users(id) on delete cascade
+);
+
+create index user_identities_user_id_idx on user_identities (user_id);
@@ -0,0 +1,13 @@
+-- personal access tokens for the JSON API, only the SHA-256 of the token is stored
+create table access_tokens (
+    id uuid primary key,
+    user_id uuid not null,
+    name varchar(255) not null,
+    token_hash bytea not null unique,
+    scopes text[] not null,
+    created_at timestamp not null default current_timestamp,
+    last_used_at timestamp,
+    constraint fk_user foreign key (user_id) references users(id) on delete cascade
+);
+
+create index access_tokens_user_id_idx on access_tokens (user_id);
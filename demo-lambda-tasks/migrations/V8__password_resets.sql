@@ -0,0 +1,11 @@
+alter table users add column email varchar(255);
+
+create unique index users_email_idx on users (lower(email));
+
+create table password_reset_tokens (
+    token_hash bytea primary key,   -- SHA-256 of the emailed token
+    user_id uuid not null,
+    created_at timestamp not null default current_timestamp,
+    expires_at timestamp not null,
+    constraint fk_user foreign key (user_id) references users(id) on delete cascade
+);
@@ -1,7 +1,9 @@
 use dotenvy::dotenv;
 use lambda_runtime::{Error, LambdaEvent, run, service_fn};
 use serde::Deserialize;
+use tokio_postgres::Client;
 use util::config::load_app_config;
+use util::tags;
 use util::tracing;
 
 refinery::embed_migrations!("migrations");
@@ -10,6 +12,7 @@ refinery::embed_migrations!("migrations");
 struct AppConfig {
     ca_certs: String,
     postgres: String,
+    login_attempts_ttl_seconds: i32,
 }
 
 #[tokio::main]
@@ -28,14 +31,124 @@ async fn main() -> Result<(), Error> {
 }
 
 async fn handler(config: &AppConfig, event: LambdaEvent<String>) -> Result<(), Error> {
-    if event.payload == "migrate" {
-        migrate(config).await?;
+    match event.payload.as_str() {
+        "migrate" => migrate(config).await?,
+        "purge_deleted_accounts" => purge_deleted_accounts(config).await?,
+        "retag_entries" => retag_entries(config).await?,
+        payload => tracing::warn!(payload, "Unknown task"),
     }
 
     Ok(())
 }
 
 async fn migrate(config: &AppConfig) -> Result<(), Error> {
+    let mut client = connect(config).await?;
+    migrations::runner().run_async(&mut client).await?;
+
+    Ok(())
+}
+
+/// Hard deletes the accounts whose grace period has ended. Their entries, sessions and other
+/// data go with them through `on delete cascade`. Audit events are kept without the user, and
+/// without the address, browser and detail that could still identify them.
+///
+/// Login attempts are keyed by name, not by user, and are forgotten here too once they can no
+/// longer lock anybody out. Failures of names that don't exist would pile up otherwise.
+async fn purge_deleted_accounts(config: &AppConfig) -> Result<(), Error> {
+    let mut client = connect(config).await?;
+
+    // current_timestamp is fixed for the transaction, so every statement sees the same accounts
+    let transaction = client.transaction().await?;
+    let purged = "select id from users \
+                  where delete_after is not null and delete_after < current_timestamp";
+    transaction
+        .execute(
+            &format!(
+                "update audit_events set ip=null, user_agent=null, detail=null \
+                 where user_id in ({})",
+                purged
+            ),
+            &[],
+        )
+        .await?;
+    transaction
+        .execute(
+            &format!(
+                "update audit_events set ip=null, user_agent=null where actor_id in ({})",
+                purged
+            ),
+            &[],
+        )
+        .await?;
+    transaction
+        .execute(
+            "delete from login_attempts where key in ( \
+                 select 'user:' || name from users \
+                 where delete_after is not null and delete_after < current_timestamp \
+             )",
+            &[],
+        )
+        .await?;
+    let deleted = transaction
+        .execute(&format!("delete from users where id in ({})", purged), &[])
+        .await?;
+    transaction.commit().await?;
+    tracing::info!(deleted, "Purged deleted accounts");
+
+    let forgotten = client
+        .execute(
+            "delete from login_attempts \
+             where last_failure_at < current_timestamp - make_interval(secs => $1) \
+             and (locked_until is null or locked_until < current_timestamp)",
+            &[&f64::from(config.login_attempts_ttl_seconds)],
+        )
+        .await?;
+    tracing::info!(forgotten, "Purged expired login attempts");
+
+    Ok(())
+}
+
+/// Parses the tags of every entry again. The backfill of the V15 migration used a regular
+/// expression whose character classes depend on the locale of the database, with the C locale it
+/// cut tags at the first non-ASCII letter.
+async fn retag_entries(config: &AppConfig) -> Result<(), Error> {
+    let mut client = connect(config).await?;
+
+    let transaction = client.transaction().await?;
+    let entries = transaction
+        .query(
+            "select user_id::text, date::text, id::text, content from entries \
+             where content like '%#%'",
+            &[],
+        )
+        .await?;
+    transaction.execute("delete from entry_tags", &[]).await?;
+    for entry in &entries {
+        let tags = tags::parse_tags(entry.get("content"));
+        if tags.is_empty() {
+            continue;
+        }
+
+        transaction
+            .execute(
+                "insert into entry_tags (user_id, date, entry_id, tag) \
+                 select $1::text::uuid, $2::text::date, $3::text::uuid, unnest($4::text[])",
+                &[
+                    &entry.get::<_, &str>("user_id"),
+                    &entry.get::<_, &str>("date"),
+                    &entry.get::<_, &str>("id"),
+                    &tags,
+                ],
+            )
+            .await?;
+    }
+    transaction.commit().await?;
+    tracing::info!(entries = entries.len(), "Retagged entries");
+
+    Ok(())
+}
+
+async fn connect(config: &AppConfig) -> Result<Client, Error> {
     use native_tls::{Certificate, TlsConnector};
     use postgres_native_tls::MakeTlsConnector;
     use std::fs;
@@ -46,7 +159,7 @@ async fn migrate(config: &AppConfig) -> Result<(), Error> {
 
     let connector = MakeTlsConnector::new(connector);
 
-    let (mut client, connection) = tokio_postgres::connect(&config.postgres, connector).await?;
+    let (client, connection) = tokio_postgres::connect(&config.postgres, connector).await?;
 
     tokio::spawn(async move {
         if let Err(e) = connection.await {
@@ -54,7 +167,5 @@ async fn migrate(config: &AppConfig) -> Result<(), Error> {
         }
     });
 
-    migrations::runner().run_async(&mut client).await?;
-
-    Ok(())
+    Ok(client)
 }
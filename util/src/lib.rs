@@ -1,2 +1,3 @@
 pub mod config;
+pub mod tags;
 pub mod tracing;
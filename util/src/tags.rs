@@ -0,0 +1,67 @@
+/// Longer tags are not taken for tags, they are more likely a mistake
+pub const MAX_TAG_LENGTH: usize = 64;
+
+/// Returns the distinct `#hashtags` of the content, lowercase and without the `#`. A tag starts
+/// at the beginning or after whitespace, so that headings, links with fragments and `C#` are not
+/// taken for one.
+pub fn parse_tags(content: &str) -> Vec<String> {
+    let mut tags: Vec<String> = content
+        .split_whitespace()
+        .filter_map(|word| {
+            let tag = word.strip_prefix('#')?;
+            let end = tag.find(|c| !is_tag_char(c)).unwrap_or(tag.len());
+            // lowercasing may add characters, the limit applies to what is stored
+            let tag = tag[..end].to_lowercase();
+            (!tag.is_empty() && tag.chars().count() <= MAX_TAG_LENGTH).then_some(tag)
+        })
+        .collect();
+
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_tags_finds_hashtags_after_whitespace() {
+        assert_eq!(
+            parse_tags("#Rust and #web-dev,\nthen #café_2 #日本"),
+            ["café_2", "rust", "web-dev", "日本"]
+        );
+    }
+
+    #[test]
+    fn parse_tags_skips_what_only_looks_like_a_tag() {
+        assert!(parse_tags("I write C# at https://example.com/#intro").is_empty());
+        assert!(parse_tags("# Heading\n## Subheading").is_empty());
+        assert!(parse_tags("##double #").is_empty());
+    }
+
+    #[test]
+    fn parse_tags_returns_each_tag_once() {
+        assert_eq!(parse_tags("#Tag #tag #TAG! #other #tag"), ["other", "tag"]);
+    }
+
+    #[test]
+    fn parse_tags_ignores_tags_longer_than_the_limit() {
+        let longest = "a".repeat(MAX_TAG_LENGTH);
+        let too_long = "a".repeat(MAX_TAG_LENGTH + 1);
+        assert_eq!(parse_tags(&format!("#{}", longest)), [longest]);
+        assert!(parse_tags(&format!("#{}", too_long)).is_empty());
+    }
+
+    #[test]
+    fn parse_tags_checks_the_length_after_lowercasing() {
+        // "İ" lowercases to two characters
+        let tag = "İ".repeat(MAX_TAG_LENGTH / 2 + 1);
+        assert_eq!(tag.chars().count(), MAX_TAG_LENGTH / 2 + 1);
+        assert!(parse_tags(&format!("#{}", tag)).is_empty());
+    }
+}